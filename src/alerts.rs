@@ -0,0 +1,285 @@
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{Result, WrapErr};
+
+use crate::{
+    config::structs::{AlertConfig, Comparison},
+    formatter::{self, fields::Field},
+    gpu_status::GpuStatusData,
+};
+
+struct Alert {
+    name: String,
+    field_name: String,
+    field: Field,
+    comparison: Comparison,
+    threshold: f32,
+    duration: Duration,
+    hysteresis: f32,
+    on_fire: Option<String>,
+    on_clear: Option<String>,
+    notify: bool,
+    state: AlertState,
+}
+
+enum AlertState {
+    Clear { pending_since: Option<Instant> },
+    Firing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertEventKind {
+    Fired,
+    Cleared,
+}
+
+/// An alert that changed state while evaluating a sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertEvent {
+    pub kind: AlertEventKind,
+    pub name: String,
+    /// The field as written in the config, e.g. `temperature:c`.
+    pub field: String,
+    /// The value of the field in its unit, `None` if it became unavailable.
+    pub value: Option<f32>,
+    pub comparison: Comparison,
+    pub threshold: f32,
+    /// The `on_fire` or `on_clear` command matching `kind`.
+    pub hook: Option<String>,
+    /// Whether to send a desktop notification.
+    pub notify: bool,
+}
+
+impl AlertEvent {
+    /// Returns a one-line description for notifications.
+    pub fn message(&self) -> String {
+        match (self.kind, self.value) {
+            (_, None) => format!("{} is unavailable", self.field),
+            (AlertEventKind::Fired, Some(value)) => format!(
+                "{} is {value} ({} {})",
+                self.field, self.comparison, self.threshold
+            ),
+            (AlertEventKind::Cleared, Some(value)) => {
+                format!("{} is back to {value}", self.field)
+            }
+        }
+    }
+}
+
+/// Evaluates the `[[alerts]]` config against successive samples.
+pub struct Alerts {
+    alerts: Vec<Alert>,
+}
+
+impl Alerts {
+    pub fn new(configs: &[AlertConfig]) -> Result<Self> {
+        let alerts = configs
+            .iter()
+            .map(|config| {
+                let field = formatter::parse_field(&config.field)
+                    .wrap_err_with(|| format!("Invalid alert field `{}`", config.field))?;
+
+                Ok(Alert {
+                    name: config.name.clone().unwrap_or_else(|| config.field.clone()),
+                    field_name: config.field.clone(),
+                    field,
+                    comparison: config.comparison,
+                    threshold: config.threshold,
+                    duration: config.duration,
+                    hysteresis: config.hysteresis,
+                    on_fire: config.on_fire.clone(),
+                    on_clear: config.on_clear.clone(),
+                    notify: config.notify,
+                    state: AlertState::Clear {
+                        pending_since: None,
+                    },
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { alerts })
+    }
+
+    /// Updates every alert with `data` sampled at `now`, returning the alerts
+    /// that fired or cleared.
+    ///
+    /// An alert fires once its comparison held for its whole duration, and
+    /// clears once the value moved back past the threshold by its hysteresis
+    /// or became unavailable.
+    pub fn evaluate(&mut self, data: &GpuStatusData, now: Instant) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for alert in &mut self.alerts {
+            let value = data.field_value(alert.field);
+
+            let kind = match (&mut alert.state, value) {
+                (AlertState::Clear { pending_since }, Some(value))
+                    if alert.comparison.matches(value, alert.threshold) =>
+                {
+                    let since = *pending_since.get_or_insert(now);
+
+                    if now.duration_since(since) < alert.duration {
+                        continue;
+                    }
+
+                    alert.state = AlertState::Firing;
+                    AlertEventKind::Fired
+                }
+                (AlertState::Clear { pending_since }, _) => {
+                    *pending_since = None;
+                    continue;
+                }
+                (AlertState::Firing, Some(value))
+                    if !alert
+                        .comparison
+                        .clears(value, alert.threshold, alert.hysteresis) =>
+                {
+                    continue;
+                }
+                (AlertState::Firing, _) => {
+                    alert.state = AlertState::Clear {
+                        pending_since: None,
+                    };
+                    AlertEventKind::Cleared
+                }
+            };
+
+            let hook = match kind {
+                AlertEventKind::Fired => alert.on_fire.clone(),
+                AlertEventKind::Cleared => alert.on_clear.clone(),
+            };
+
+            events.push(AlertEvent {
+                kind,
+                name: alert.name.clone(),
+                field: alert.field_name.clone(),
+                value,
+                comparison: alert.comparison,
+                threshold: alert.threshold,
+                hook,
+                notify: alert.notify,
+            });
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::thermodynamic_temperature::degree_celsius;
+
+    use super::*;
+    use crate::gpu_status::Temperature;
+
+    fn alerts(duration: Duration, hysteresis: f32) -> Alerts {
+        Alerts::new(&[AlertConfig {
+            name: None,
+            field: "temperature:c".to_string(),
+            comparison: Comparison::AtLeast,
+            threshold: 90.0,
+            duration,
+            hysteresis,
+            on_fire: None,
+            on_clear: None,
+            notify: true,
+        }])
+        .unwrap()
+    }
+
+    fn sample(celsius: Option<f32>) -> GpuStatusData {
+        GpuStatusData {
+            temperature: celsius.map(Temperature::new::<degree_celsius>),
+            ..Default::default()
+        }
+    }
+
+    fn kinds(events: Vec<AlertEvent>) -> Vec<AlertEventKind> {
+        events.into_iter().map(|e| e.kind).collect()
+    }
+
+    /// Test that an alert only fires once the comparison held for its duration.
+    #[test]
+    fn test_fires_after_duration() {
+        let mut alerts = alerts(Duration::from_secs(30), 0.0);
+        let start = Instant::now();
+
+        assert!(alerts.evaluate(&sample(Some(91.0)), start).is_empty());
+        assert!(
+            alerts
+                .evaluate(&sample(Some(92.0)), start + Duration::from_secs(10))
+                .is_empty()
+        );
+
+        // Dropping below the threshold restarts the timer.
+        assert!(
+            alerts
+                .evaluate(&sample(Some(80.0)), start + Duration::from_secs(20))
+                .is_empty()
+        );
+        assert!(
+            alerts
+                .evaluate(&sample(Some(91.0)), start + Duration::from_secs(40))
+                .is_empty()
+        );
+
+        let events = alerts.evaluate(&sample(Some(91.0)), start + Duration::from_secs(70));
+        assert_eq!(events[0].message(), "temperature:c is 91 (>= 90)");
+        assert_eq!(kinds(events), vec![AlertEventKind::Fired]);
+
+        // Firing alerts don't fire again.
+        assert!(
+            alerts
+                .evaluate(&sample(Some(95.0)), start + Duration::from_secs(80))
+                .is_empty()
+        );
+    }
+
+    /// Test that a firing alert only clears past the hysteresis.
+    #[test]
+    fn test_clears_with_hysteresis() {
+        let mut alerts = alerts(Duration::ZERO, 5.0);
+        let now = Instant::now();
+
+        assert_eq!(
+            kinds(alerts.evaluate(&sample(Some(90.0)), now)),
+            vec![AlertEventKind::Fired]
+        );
+        assert!(alerts.evaluate(&sample(Some(86.0)), now).is_empty());
+        assert_eq!(
+            kinds(alerts.evaluate(&sample(Some(84.0)), now)),
+            vec![AlertEventKind::Cleared]
+        );
+    }
+
+    /// Test that a firing alert clears when its value becomes unavailable.
+    #[test]
+    fn test_clears_when_unavailable() {
+        let mut alerts = alerts(Duration::ZERO, 0.0);
+        let now = Instant::now();
+
+        alerts.evaluate(&sample(Some(95.0)), now);
+
+        let events = alerts.evaluate(&sample(None), now);
+        assert_eq!(events[0].message(), "temperature:c is unavailable");
+        assert_eq!(kinds(events), vec![AlertEventKind::Cleared]);
+    }
+
+    #[test]
+    fn test_invalid_field() {
+        assert!(
+            Alerts::new(&[AlertConfig {
+                name: None,
+                field: "temperature".to_string(),
+                comparison: Comparison::Above,
+                threshold: 90.0,
+                duration: Duration::ZERO,
+                hysteresis: 0.0,
+                on_fire: None,
+                on_clear: None,
+                notify: true,
+            }])
+            .is_err()
+        );
+    }
+}
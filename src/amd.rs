@@ -1,47 +1,133 @@
-use std::path::PathBuf;
+use std::{fs, path::PathBuf, sync::Arc};
 
-use amdgpu_sysfs::{gpu_handle::GpuHandle, hw_mon::HwMon};
-use color_eyre::eyre::{Result, eyre};
+use amdgpu_sysfs::{gpu_handle::GpuHandle, hw_mon::HwMon, sysfs::SysFS};
+use color_eyre::eyre::{Report, Result, eyre};
 use regex::Regex;
 use uom::si::{
     f32::Information, f32::Power, information::byte, power::watt,
     thermodynamic_temperature::degree_celsius,
 };
 
-use crate::gpu_status::{GpuStatus, GpuStatusData, Temperature};
+use crate::{
+    fdinfo::DrmClients,
+    formatter::fields::MemField,
+    fs_root::FsRoot,
+    gpu_metrics,
+    gpu_status::{DeviceInfo, DeviceLost, GpuStatus, GpuStatusData, Temperature},
+    pcie_bw::PcieBandwidth,
+};
 
 pub struct AmdGpuStatus {
-    amd_sys_fs: &'static AmdSysFS,
+    amd_sys_fs: AmdSysFS,
+    device_info: Arc<DeviceInfo>,
+    /// `None` if the GPU has no PCI bus ID to match fdinfo against.
+    clients: Option<DrmClients>,
+    /// `None` if the GPU has no `pcie_bw`, e.g. APUs, or if the throughput
+    /// isn't needed.
+    pcie_bw: Option<PcieBandwidth>,
 }
 
 impl AmdGpuStatus {
-    pub const fn new(amd_sys_fs: &'static AmdSysFS) -> Result<Self> {
-        Ok(Self { amd_sys_fs })
+    /// Reading `pcie_bw` blocks the driver for a second, so it is only read
+    /// in the background if `measure_throughput` is set.
+    pub fn new(amd_sys_fs: AmdSysFS, measure_throughput: bool) -> Result<Self> {
+        let gpu_handle = &amd_sys_fs.gpu_handle;
+        let device_info = DeviceInfo {
+            vendor: "amd".to_string(),
+            pci_bus_id: gpu_handle.get_pci_slot_name().map(str::to_owned),
+            // Older GPUs have no product name or unique ID.
+            name: gpu_handle.read_file("product_name").ok(),
+            // amdgpu ships with the kernel.
+            driver_version: fs::read_to_string(amd_sys_fs.root.proc().join("sys/kernel/osrelease"))
+                .ok()
+                .map(|v| v.trim().to_string()),
+            vbios_version: gpu_handle.get_vbios_version().ok(),
+            uuid: gpu_handle.read_file("unique_id").ok(),
+        };
+
+        let clients = device_info
+            .pci_bus_id
+            .as_deref()
+            .map(|bus_id| DrmClients::new("amdgpu", bus_id, amd_sys_fs.root.clone()));
+
+        let pcie_bw = device_info
+            .pci_bus_id
+            .clone()
+            .filter(|_| measure_throughput)
+            .and_then(|bus_id| PcieBandwidth::spawn(amd_sys_fs.root.clone(), bus_id));
+
+        Ok(Self {
+            amd_sys_fs,
+            device_info: Arc::new(device_info),
+            clients,
+            pcie_bw,
+        })
     }
 }
 
 impl GpuStatus for AmdGpuStatus {
     fn compute(&self) -> Result<GpuStatusData> {
+        // Reading the sensors would wake up a suspended GPU.
+        if let Some(ref bus_id) = self.device_info.pci_bus_id
+            && !self.amd_sys_fs.root.is_powered_on(bus_id)
+        {
+            return Ok(GpuStatusData::default());
+        }
+
+        self.sample()
+    }
+
+    fn compute_force(&self) -> Result<GpuStatusData> {
+        self.sample()
+    }
+
+    fn device_info(&self) -> Arc<DeviceInfo> {
+        Arc::clone(&self.device_info)
+    }
+
+    /// `pcie_bw` has no reading until the first read of the background
+    /// thread completes.
+    fn background_fields(&self) -> Vec<MemField> {
+        match self.pcie_bw {
+            Some(_) => vec![MemField::Tx, MemField::Rx],
+            None => Vec::new(),
+        }
+    }
+}
+
+impl AmdGpuStatus {
+    fn sample(&self) -> Result<GpuStatusData> {
         let gpu_handle = &self.amd_sys_fs.gpu_handle;
-        let hw_mon = &gpu_handle.hw_monitors[0];
+        let hw_mon = gpu_handle.hw_monitors.first();
 
-        let temps = hw_mon.get_temps();
-        const TEMP_SENSOR_NAME: &str = "edge";
-        let temp = temps
-            .iter()
-            .find(|t| t.0 == TEMP_SENSOR_NAME)
-            .ok_or(eyre!(format!(
-                "No \"{}\" temperature sensor found",
-                TEMP_SENSOR_NAME
-            )))?
-            .1
-            .current;
+        // The device and its sensors disappear while the driver reloads. A
+        // missing sensor on its own only means the GPU doesn't have it.
+        if !gpu_handle.get_path().exists() || hw_mon.is_some_and(|h| !h.get_path().exists()) {
+            return Err(Report::new(DeviceLost).wrap_err("The GPU's sysfs directory is gone"));
+        }
+
+        let temps = hw_mon.map(HwMon::get_temps).unwrap_or_default();
+        let hw_mon_temp = |label: &str| {
+            temps
+                .get(label)
+                .and_then(|t| t.current)
+                .map(Temperature::new::<degree_celsius>)
+        };
+
+        // Fills in what sysfs lacks, such as video engine utilization.
+        let metrics = fs::read(gpu_handle.get_path().join("gpu_metrics"))
+            .ok()
+            .and_then(|blob| gpu_metrics::parse(&blob))
+            .unwrap_or_default();
+
+        let processes = self.clients.as_ref().and_then(DrmClients::processes);
+        let pcie_throughput = self.pcie_bw.as_ref().and_then(PcieBandwidth::latest);
 
         Ok(GpuStatusData {
             powered_on: true,
-            has_running_processes: true, /* TODO: temporarily set to true until AMD GPU process
-                                          * detection is implemented */
-            gpu_utilization: gpu_handle.get_busy_percent().ok(),
+            // Assume the GPU is in use if the processes can't be listed.
+            has_running_processes: processes.as_ref().is_none_or(|p| !p.is_empty()),
+            gpu_utilization: gpu_handle.get_busy_percent().ok().or(metrics.gfx_activity),
             mem_used: gpu_handle
                 .get_used_vram()
                 .ok()
@@ -50,13 +136,46 @@ impl GpuStatus for AmdGpuStatus {
                 .get_total_vram()
                 .ok()
                 .map(|v| Information::new::<byte>(v as f32)),
-            temperature: temp.map(Temperature::new::<degree_celsius>),
-            power: hw_mon
-                .get_power_input()
+            gtt_used: read_bytes(gpu_handle, "mem_info_gtt_used"),
+            gtt_total: read_bytes(gpu_handle, "mem_info_gtt_total"),
+            vis_vram_used: read_bytes(gpu_handle, "mem_info_vis_vram_used"),
+            mem_rw: gpu_handle
+                .read_file_parsed("mem_busy_percent")
                 .ok()
-                .map(|v| Power::new::<watt>(v as f32)),
+                .or(metrics.umc_activity),
+            // Decoding and encoding share the VCN engine.
+            decoder_utilization: metrics.vcn_activity,
+            encoder_utilization: metrics.vcn_activity,
+            temperature: hw_mon_temp("edge").or(metrics.temperature),
+            temperature_hotspot: hw_mon_temp("junction").or(metrics.temperature_hotspot),
+            temperature_mem: hw_mon_temp("mem").or(metrics.temperature_mem),
+            power: hw_mon
+                .and_then(|h| h.get_power_input().ok())
+                .map(|v| Power::new::<watt>(v as f32))
+                .or(metrics.socket_power),
+            // Only some APUs have an energy counter.
+            energy_counter: hw_mon.and_then(|h| h.read_file_parsed::<u64, _>("energy1_input").ok()),
             p_level: gpu_handle.get_power_force_performance_level().ok(),
-            fan_speed: fan_percentage(hw_mon).ok(),
+            fan_speed: hw_mon.and_then(|h| fan_percentage(h).ok()),
+            pcie_gen: gpu_handle
+                .get_current_link_speed()
+                .ok()
+                .and_then(|s| pcie_gen(&s)),
+            pcie_width: gpu_handle
+                .get_current_link_width()
+                .ok()
+                .and_then(|w| w.parse().ok()),
+            pcie_max_gen: gpu_handle
+                .get_max_link_speed()
+                .ok()
+                .and_then(|s| pcie_gen(&s)),
+            pcie_max_width: gpu_handle
+                .get_max_link_width()
+                .ok()
+                .and_then(|w| w.parse().ok()),
+            tx: pcie_throughput.map(|(tx, _)| tx),
+            rx: pcie_throughput.map(|(_, rx)| rx),
+            processes,
             ..Default::default()
         })
     }
@@ -64,26 +183,32 @@ impl GpuStatus for AmdGpuStatus {
 
 pub struct AmdSysFS {
     gpu_handle: GpuHandle,
+    root: FsRoot,
 }
 
 impl AmdSysFS {
     pub fn init() -> Result<Self> {
-        let drm_gpus = Self::get_drm_gpus()?;
-
-        if drm_gpus.is_empty() {
-            return Err(eyre!("No AMD GPU found"));
-        }
+        Self::init_at(FsRoot::default())
+    }
 
-        let gpu_handle = GpuHandle::new_from_path(drm_gpus[0].clone())?;
+    /// Uses the first card driven by `amdgpu`, in card number order.
+    fn init_at(root: FsRoot) -> Result<Self> {
+        let gpu_handle = Self::get_drm_gpus(&root)?
+            .into_iter()
+            .filter_map(|path| GpuHandle::new_from_path(path).ok())
+            .find(|handle| handle.get_driver() == "amdgpu")
+            .ok_or(eyre!("No AMD GPU found"))?;
 
-        Ok(Self { gpu_handle })
+        Ok(Self { gpu_handle, root })
     }
 
-    fn get_drm_gpus() -> Result<Vec<PathBuf>> {
-        let drm_dir = PathBuf::from("/sys/class/drm");
+    /// Returns the `device` directory of every DRM card, sorted by card
+    /// number.
+    fn get_drm_gpus(root: &FsRoot) -> Result<Vec<PathBuf>> {
+        let drm_dir = root.drm_class();
         let mut drm_gpus = Vec::new();
 
-        let card_regex = Regex::new(r"^card[0-9]*$")?;
+        let card_regex = Regex::new(r"^card([0-9]+)$")?;
 
         for entry in drm_dir.read_dir()? {
             let entry = entry?;
@@ -96,20 +221,214 @@ impl AmdSysFS {
                     .to_str()
                     .ok_or(eyre!("Path isn't a valid UTF-8"))?;
 
-                if card_regex.is_match(drm_device) {
+                if let Some(number) = card_regex.captures(drm_device) {
+                    let number: u32 = number[1].parse()?;
                     path.push(PathBuf::from("device"));
-                    drm_gpus.push(path);
+                    drm_gpus.push((number, path));
                 }
             }
         }
 
-        Ok(drm_gpus)
+        drm_gpus.sort_unstable_by_key(|(number, _)| *number);
+
+        Ok(drm_gpus.into_iter().map(|(_, path)| path).collect())
     }
 }
 
+/// Returns the PCIe generation of a sysfs link speed such as `16.0 GT/s PCIe`.
+fn pcie_gen(speed: &str) -> Option<u8> {
+    let transfers: f32 = speed.split_whitespace().next()?.parse().ok()?;
+
+    // Generation 1 runs at 2.5 GT/s, which then doubles each generation.
+    let generation = (transfers / 2.5).log2().round() as i32 + 1;
+
+    (1..=u8::MAX as i32)
+        .contains(&generation)
+        .then_some(generation as u8)
+}
+
+/// Reads a sysfs file holding a number of bytes.
+fn read_bytes(gpu_handle: &GpuHandle, file: &str) -> Option<Information> {
+    gpu_handle
+        .read_file_parsed::<u64, _>(file)
+        .ok()
+        .map(|v| Information::new::<byte>(v as f32))
+}
+
 fn fan_percentage(hw_mon: &HwMon) -> Result<u8, amdgpu_sysfs::error::Error> {
     let current_rpm = hw_mon.get_fan_current()? as f32;
     let max_rpm = hw_mon.get_fan_max()? as f32;
 
     Ok((current_rpm / max_rpm * 100.0).round().clamp(0.0, 100.0) as u8)
 }
+
+#[cfg(test)]
+mod tests {
+    use uom::si::information::mebibyte;
+
+    use super::*;
+    use crate::fs_root;
+
+    fn status(fixture: &str) -> AmdGpuStatus {
+        let amd_sys_fs = AmdSysFS::init_at(fs_root::fixture(fixture)).unwrap();
+
+        AmdGpuStatus::new(amd_sys_fs, true).unwrap()
+    }
+
+    #[test]
+    fn test_dgpu() {
+        let status = status("amd-dgpu");
+        assert_eq!(
+            status.device_info().pci_bus_id.as_deref(),
+            Some("0000:03:00.0")
+        );
+        assert_eq!(
+            status.device_info().name.as_deref(),
+            Some("AMD Radeon RX 7800 XT")
+        );
+        assert_eq!(
+            status.device_info().driver_version.as_deref(),
+            Some("6.17.1-arch1-1")
+        );
+        assert_eq!(
+            status.device_info().vbios_version.as_deref(),
+            Some("113-APM7489-DS2-100")
+        );
+
+        let data = status.compute().unwrap();
+        assert!(data.powered_on);
+        assert_eq!(data.gpu_utilization, Some(37));
+        assert_eq!(data.mem_used.map(|v| v.get::<mebibyte>()), Some(2048.0));
+        assert_eq!(data.gtt_used.map(|v| v.get::<mebibyte>()), Some(64.0));
+        assert_eq!(
+            data.vis_vram_used.map(|v| v.get::<mebibyte>()),
+            Some(1024.0)
+        );
+        assert_eq!(
+            data.temperature.map(|v| v.get::<degree_celsius>()),
+            Some(45.0)
+        );
+        assert_eq!(data.power.map(|v| v.get::<watt>()), Some(87.0));
+        assert_eq!(data.fan_speed, Some(36));
+        // Hotspot from hwmon, memory temperature and the video engine from
+        // `gpu_metrics`.
+        assert_eq!(
+            (
+                data.temperature_hotspot.map(|v| v.get::<degree_celsius>()),
+                data.temperature_mem.map(|v| v.get::<degree_celsius>())
+            ),
+            (Some(52.0), Some(56.0))
+        );
+        assert_eq!(data.mem_rw, Some(18));
+        assert_eq!(
+            (data.decoder_utilization, data.encoder_utilization),
+            (Some(5), Some(5))
+        );
+        assert_eq!(
+            (
+                data.pcie_gen,
+                data.pcie_width,
+                data.pcie_max_gen,
+                data.pcie_max_width
+            ),
+            (Some(3), Some(4), Some(4), Some(16))
+        );
+
+        assert!(data.has_running_processes);
+        let processes = data.processes.unwrap();
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].name, "gnome-shell");
+        assert_eq!(processes[0].vram.map(|v| v.get::<mebibyte>()), Some(64.0));
+
+        // Whether or not `pcie_bw` was read yet.
+        assert_eq!(status.background_fields(), [MemField::Tx, MemField::Rx]);
+
+        // Without a format using them, `pcie_bw` isn't read at all.
+        let amd_sys_fs = AmdSysFS::init_at(fs_root::fixture("amd-dgpu")).unwrap();
+        let status = AmdGpuStatus::new(amd_sys_fs, false).unwrap();
+        assert!(status.background_fields().is_empty());
+        assert_eq!(status.compute().unwrap().tx, None);
+    }
+
+    #[test]
+    fn test_apu() {
+        let data = status("amd-apu").compute().unwrap();
+
+        assert!(data.powered_on);
+        assert_eq!(data.mem_total.map(|v| v.get::<mebibyte>()), Some(512.0));
+        assert_eq!(data.gtt_used.map(|v| v.get::<mebibyte>()), Some(3072.0));
+        assert_eq!(data.gtt_total.map(|v| v.get::<mebibyte>()), Some(15872.0));
+        assert_eq!(data.vis_vram_used.map(|v| v.get::<mebibyte>()), Some(128.0));
+        assert_eq!(data.power.map(|v| v.get::<watt>()), Some(9.0));
+        assert_eq!(data.fan_speed, None);
+        // APUs have no memory controller utilization.
+        assert_eq!(data.mem_rw, None);
+        assert_eq!(data.decoder_utilization, Some(8));
+        // Without a readable /proc the GPU counts as in use.
+        assert!(data.has_running_processes);
+        assert_eq!(data.processes, None);
+    }
+
+    /// Test that a GPU without an edge sensor isn't treated as lost and
+    /// gets its temperature from `gpu_metrics`.
+    #[test]
+    fn test_no_edge_sensor() {
+        let data = status("amd-no-edge").compute().unwrap();
+
+        assert_eq!(
+            data.temperature.map(|v| v.get::<degree_celsius>()),
+            Some(51.25)
+        );
+        assert_eq!(
+            data.temperature_hotspot.map(|v| v.get::<degree_celsius>()),
+            Some(52.0)
+        );
+    }
+
+    /// Test that a suspended GPU is reported as off without reading sensors.
+    #[test]
+    fn test_suspended() {
+        let status = status("amd-suspended");
+
+        let data = status.compute().unwrap();
+        assert!(!data.powered_on);
+        assert_eq!(data.gpu_utilization, None);
+
+        assert_eq!(status.compute_force().unwrap().gpu_utilization, Some(0));
+    }
+
+    /// Test that cards not driven by `amdgpu` are skipped and that the first
+    /// AMD card is picked by number, not by name.
+    #[test]
+    fn test_multi_card() {
+        let root = fs_root::fixture("multi-card");
+
+        let cards = AmdSysFS::get_drm_gpus(&root).unwrap();
+        let names: Vec<_> = cards
+            .iter()
+            .map(|p| p.parent().unwrap().file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["card0", "card2", "card10"]);
+
+        let status = status("multi-card");
+        assert_eq!(
+            status.device_info().pci_bus_id.as_deref(),
+            Some("0000:0a:00.0")
+        );
+        assert_eq!(status.compute().unwrap().gpu_utilization, Some(5));
+    }
+
+    #[test]
+    fn test_pcie_gen() {
+        assert_eq!(pcie_gen("2.5 GT/s PCIe"), Some(1));
+        assert_eq!(pcie_gen("8.0 GT/s PCIe"), Some(3));
+        assert_eq!(pcie_gen("16.0 GT/s PCIe"), Some(4));
+        assert_eq!(pcie_gen("64.0 GT/s PCIe"), Some(6));
+        assert_eq!(pcie_gen("Unknown"), None);
+    }
+
+    #[test]
+    fn test_no_amd_gpu() {
+        assert!(AmdSysFS::init_at(fs_root::fixture("nvidia-busy")).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use color_eyre::eyre::{Result, WrapErr};
+
+use crate::{
+    config::structs::{ClassConfig, Comparison},
+    formatter::{self, fields::Field},
+    gpu_status::GpuStatusData,
+};
+
+struct ClassRule {
+    field: Field,
+    comparison: Comparison,
+    threshold: f32,
+    class: String,
+}
+
+/// Picks the CSS class of the module from the `[[classes]]` config.
+pub struct ClassRules {
+    rules: Vec<ClassRule>,
+}
+
+impl ClassRules {
+    pub fn new(configs: &[ClassConfig]) -> Result<Self> {
+        let rules = configs
+            .iter()
+            .map(|config| {
+                let field = formatter::parse_field(&config.field)
+                    .wrap_err_with(|| format!("Invalid class field `{}`", config.field))?;
+
+                Ok(ClassRule {
+                    field,
+                    comparison: config.comparison,
+                    threshold: config.threshold,
+                    class: config.class.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { rules })
+    }
+
+    /// Returns the class of the first rule matching `data`.
+    ///
+    /// Rules on unavailable fields never match.
+    pub fn class(&self, data: &GpuStatusData) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|rule| {
+                data.field_value(rule.field)
+                    .is_some_and(|value| rule.comparison.matches(value, rule.threshold))
+            })
+            .map(|rule| rule.class.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(field: &str, comparison: Comparison, threshold: f32, class: &str) -> ClassConfig {
+        ClassConfig {
+            field: field.to_string(),
+            comparison,
+            threshold,
+            class: class.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_class() {
+        let rules = ClassRules::new(&[
+            rule("temperature:c", Comparison::AtLeast, 90.0, "hot"),
+            rule("pcie_width", Comparison::Below, 16.0, "degraded"),
+        ])
+        .unwrap();
+
+        let data = GpuStatusData {
+            pcie_width: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(rules.class(&data), Some("degraded"));
+
+        let data = GpuStatusData {
+            pcie_width: Some(16),
+            ..Default::default()
+        };
+        assert_eq!(rules.class(&data), None);
+
+        assert_eq!(rules.class(&GpuStatusData::default()), None);
+    }
+
+    #[test]
+    fn test_invalid_field() {
+        assert!(ClassRules::new(&[rule("pcie", Comparison::Below, 16.0, "x")]).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 pub mod structs;
 
+use std::path::{Path, PathBuf};
+
 use color_eyre::{Result, eyre::eyre};
 use etcetera::{BaseStrategy, base_strategy::Xdg};
 
@@ -7,16 +9,20 @@ use crate::config::structs::ConfigFile;
 
 const EXAMPLE_CONFIG: &str = include_str!("../../config.example.toml");
 
-pub fn get_or_init_config() -> Result<ConfigFile> {
-    let config_dir = Xdg::new()?.config_dir();
-
-    let config_path = config_dir.join("gpu_usage_waybar.toml");
+/// Returns `custom`, or `$XDG_CONFIG_HOME/gpu_usage_waybar.toml` if unset.
+pub fn config_path(custom: Option<&Path>) -> Result<PathBuf> {
+    match custom {
+        Some(path) => Ok(path.to_path_buf()),
+        None => Ok(Xdg::new()?.config_dir().join("gpu_usage_waybar.toml")),
+    }
+}
 
+pub fn get_or_init_config(config_path: &Path) -> Result<ConfigFile> {
     if !config_path.exists() {
-        std::fs::write(&config_path, EXAMPLE_CONFIG)?;
+        std::fs::write(config_path, EXAMPLE_CONFIG)?;
     }
 
-    let config_str = std::fs::read_to_string(&config_path)?;
+    let config_str = std::fs::read_to_string(config_path)?;
 
     let config: ConfigFile =
         toml::de::from_str(&config_str).map_err(|e| eyre!("Failed to parse config file: {}", e))?;
@@ -1,11 +1,19 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
 use color_eyre::Result;
 use serde::Deserialize;
 use smart_default::SmartDefault;
+use strum::Display;
 
 use crate::{
     Args,
-    formatter::{self, FormatSegments, fields::Field},
-    gpu_status::GpuStatusData,
+    formatter::{
+        self, FormatSegments,
+        fields::{Field, MemField},
+    },
+    gpu_status::{DeviceInfo, GpuStatusData},
+    output::OutputKind,
+    recorder::RecordFormat,
 };
 
 #[derive(Default, Deserialize)]
@@ -15,6 +23,12 @@ pub struct ConfigFile {
     pub general: GeneralConfig,
     pub text: TextConfig,
     pub tooltip: TooltipConfig,
+    pub alerts: Vec<AlertConfig>,
+    pub classes: Vec<ClassConfig>,
+    pub hooks: HooksConfig,
+    pub logging: LoggingConfig,
+    pub errors: ErrorsConfig,
+    pub energy: EnergyConfig,
 }
 
 impl ConfigFile {
@@ -28,6 +42,12 @@ impl ConfigFile {
         if let Some(ref tooltip_format) = args.tooltip_format {
             self.tooltip.format = Some(tooltip_format.to_owned());
         }
+        if let Some(output) = args.output {
+            self.general.output = output;
+        }
+        if args.plain {
+            self.general.output = OutputKind::Plain;
+        }
 
         Ok(())
     }
@@ -47,6 +67,9 @@ pub struct TextConfig {
 pub struct GeneralConfig {
     #[default(1000)]
     pub interval: u64,
+    pub output: OutputKind,
+    /// Colors of the classes for bars without CSS, e.g. `hot = "#FF0000"`.
+    pub colors: HashMap<String, String>,
 }
 
 #[derive(Deserialize, SmartDefault)]
@@ -84,8 +107,15 @@ RX: {rx:MiB.3} MiB/s";
     ///
     /// This function modifies the `format` field in place.
     /// If a line contains **any** placeholder without a corresponding value
-    /// in `data`, that entire line is removed from the format.
-    pub fn retain_lines_with_values(&mut self, data: &GpuStatusData) {
+    /// in `data` or `device`, that entire line is removed from the format.
+    /// Fields measured in the `background` are kept, as their value may only
+    /// be missing yet.
+    pub fn retain_lines_with_values(
+        &mut self,
+        data: &GpuStatusData,
+        device: &DeviceInfo,
+        background: &[MemField],
+    ) {
         let mut result = String::new();
         let re = formatter::get_regex();
 
@@ -93,7 +123,10 @@ RX: {rx:MiB.3} MiB/s";
             // Check if ANY field string is invalid
             let has_unavailable = re.captures_iter(line).any(|caps| {
                 let format_segments = FormatSegments::from_caps_unchecked(&caps);
-                Field::try_from(format_segments).map_or(true, |f| data.is_field_unavailable(f))
+                Field::try_from(format_segments).map_or(true, |f| match f {
+                    Field::Mem { field, .. } if background.contains(&field) => false,
+                    f => data.is_field_unavailable(device, f),
+                })
             });
 
             if has_unavailable {
@@ -107,11 +140,166 @@ RX: {rx:MiB.3} MiB/s";
     }
 }
 
+/// An `[[alerts]]` entry.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+    /// Shown as the notification summary, defaults to `field`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// A field with its unit as in format strings, e.g. `temperature:c`.
+    pub field: String,
+    pub comparison: Comparison,
+    pub threshold: f32,
+    /// How long the comparison must hold before the alert fires.
+    #[serde(default, rename = "for", with = "humantime_serde")]
+    pub duration: Duration,
+    /// How far the value must move back past `threshold` to clear the alert.
+    #[serde(default)]
+    pub hysteresis: f32,
+    /// Shell command run when the alert fires.
+    #[serde(default)]
+    pub on_fire: Option<String>,
+    /// Shell command run when the alert clears.
+    #[serde(default)]
+    pub on_clear: Option<String>,
+    /// Whether to send a desktop notification when the alert fires or clears.
+    #[serde(default = "notify_default")]
+    pub notify: bool,
+}
+
+fn notify_default() -> bool {
+    true
+}
+
+/// Shell commands run when the GPU changes state.
+#[derive(Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub on_power_on: Option<String>,
+    pub on_power_off: Option<String>,
+    pub on_idle: Option<String>,
+    pub on_busy: Option<String>,
+}
+
+/// Recording of every sample to disk.
+#[derive(Deserialize, SmartDefault)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// File to append samples to, logging is disabled when unset.
+    pub path: Option<PathBuf>,
+    pub format: RecordFormat,
+    /// Rotate the file once it reaches this size in bytes.
+    pub max_size: Option<u64>,
+    /// Rotate the file once it is older than this.
+    #[serde(with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+    /// Number of rotated files to keep.
+    #[default(5)]
+    pub keep: usize,
+}
+
+/// A `[[classes]]` entry, setting a CSS class on the module.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClassConfig {
+    /// A field with its unit as in format strings, e.g. `temperature:c`.
+    pub field: String,
+    pub comparison: Comparison,
+    pub threshold: f32,
+    pub class: String,
+}
+
+/// What to show while the GPU can't be read.
+#[derive(Deserialize, SmartDefault)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct ErrorsConfig {
+    /// Text shown instead of the format, the tooltip shows the error.
+    #[default("Error")]
+    pub text: String,
+    /// CSS class set on the module.
+    #[default("error")]
+    pub class: String,
+    /// Text shown while the GPU is unplugged.
+    #[default("Disconnected")]
+    pub disconnected_text: String,
+    /// CSS class set on the module while the GPU is unplugged.
+    #[default("disconnected")]
+    pub disconnected_class: String,
+    /// Longest delay between two attempts to read the GPU again.
+    #[default(Duration::from_secs(60))]
+    #[serde(with = "humantime_serde")]
+    pub max_backoff: Duration,
+}
+
+/// The `[energy]` section, for the `{energy}` and `{cost}` fields.
+#[derive(Deserialize, SmartDefault)]
+#[serde(deny_unknown_fields)]
+#[serde(default)]
+pub struct EnergyConfig {
+    pub since: EnergySince,
+    /// Price of a kWh, `{cost}` is unavailable without it.
+    pub price: Option<f32>,
+}
+
+/// When `{energy}` starts counting from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnergySince {
+    /// When the program started.
+    #[default]
+    Start,
+    /// The last local midnight.
+    Midnight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Display)]
+pub enum Comparison {
+    #[serde(rename = ">")]
+    #[strum(serialize = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    #[strum(serialize = ">=")]
+    AtLeast,
+    #[serde(rename = "<")]
+    #[strum(serialize = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    #[strum(serialize = "<=")]
+    AtMost,
+}
+
+impl Comparison {
+    pub fn matches(self, value: f32, threshold: f32) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::AtLeast => value >= threshold,
+            Comparison::Below => value < threshold,
+            Comparison::AtMost => value <= threshold,
+        }
+    }
+
+    /// Returns `true` if `value` no longer matches even when moved
+    /// `hysteresis` towards `threshold`.
+    pub fn clears(self, value: f32, threshold: f32, hysteresis: f32) -> bool {
+        let value = match self {
+            Comparison::Above | Comparison::AtLeast => value + hysteresis,
+            Comparison::Below | Comparison::AtMost => value - hysteresis,
+        };
+
+        !self.matches(value, threshold)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
         config::structs::TooltipConfig,
-        gpu_status::{GpuStatusData, PState},
+        formatter::fields::MemField,
+        gpu_status::{DeviceInfo, GpuStatusData, PState},
     };
     use uom::si::{f32::Information, information::mebibyte};
 
@@ -138,7 +326,7 @@ RX: {rx:MiB.0} MiB/s"
             ),
         };
 
-        config.retain_lines_with_values(&data);
+        config.retain_lines_with_values(&data, &DeviceInfo::default(), &[]);
 
         assert_eq!(
             config.format.unwrap(),
@@ -148,6 +336,23 @@ RX: {rx:MiB.0} MiB/s"
         );
     }
 
+    /// Test that lines of fields measured in the background are kept before
+    /// their first value.
+    #[test]
+    fn test_retain_background_fields() {
+        let mut config = TooltipConfig {
+            format: Some("FAN SPEED: {fan_speed}%\nTX: {tx:MiB.0} MiB/s".to_string()),
+        };
+
+        config.retain_lines_with_values(
+            &GpuStatusData::default(),
+            &DeviceInfo::default(),
+            &[MemField::Tx, MemField::Rx],
+        );
+
+        assert_eq!(config.format.unwrap(), "TX: {tx:MiB.0} MiB/s");
+    }
+
     /// Test that lines with multiple placeholders are dropped if any of them
     /// have no value.
     #[test]
@@ -168,7 +373,7 @@ RX: {rx:MiB.0} MiB/s"
             format: Some(format.to_string()),
         };
 
-        config.retain_lines_with_values(&data);
+        config.retain_lines_with_values(&data, &DeviceInfo::default(), &[]);
         // Both lines should be dropped because each has at least one unavailable field
         assert_eq!(config.format, Some("".to_string()));
     }
@@ -0,0 +1,546 @@
+use std::{
+    cell::RefCell,
+    env,
+    ffi::OsString,
+    fs::{self, File, TryLockError},
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        net::{UnixListener, UnixStream},
+        process::CommandExt,
+    },
+    path::{Path, PathBuf},
+    process::{self, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{Report, Result, eyre};
+use etcetera::{BaseStrategy, base_strategy::Xdg};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    formatter::fields::MemField,
+    gpu_status::{DeviceInfo, Disconnected, GpuStatus, GpuStatusData},
+};
+
+const SOCKET_NAME: &str = "gpu-usage-waybar.sock";
+
+/// How long a daemon started on demand keeps running without subscribers.
+const UNUSED_GRACE: Duration = Duration::from_secs(10);
+/// Subscribers that can't take a sample within this time are dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+const CONNECT_ATTEMPTS: u32 = 50;
+const CONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// Returns the daemon socket path, in `$XDG_RUNTIME_DIR` when it is set.
+pub fn socket_path() -> Result<PathBuf> {
+    let runtime_dir = Xdg::new()?.runtime_dir().unwrap_or_else(env::temp_dir);
+
+    Ok(runtime_dir.join(SOCKET_NAME))
+}
+
+/// A line sent to subscribers.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Message {
+    /// The GPU couldn't be read. `disconnected` is the PCI bus ID of a GPU
+    /// that is [Disconnected].
+    Error {
+        error: String,
+        #[serde(default)]
+        disconnected: Option<String>,
+    },
+    /// The identity of the GPU the following samples come from, and its
+    /// [GpuStatus::background_fields].
+    Device {
+        device: DeviceInfo,
+        #[serde(default)]
+        background_fields: Vec<String>,
+    },
+    Sample(Box<GpuStatusData>),
+}
+
+impl Message {
+    fn error(e: &Report) -> Self {
+        Message::Error {
+            error: format!("{e:#}"),
+            disconnected: e
+                .downcast_ref::<Disconnected>()
+                .map(|d| d.pci_bus_id.clone()),
+        }
+    }
+}
+
+/// Polls `gpu_status_handler` and publishes each sample to every subscriber
+/// of the socket at [socket_path].
+///
+/// # Protocol
+///
+/// Each line is a [GpuStatusData] serialized as JSON. A new subscriber
+/// first gets `{"device": <DeviceInfo>, "background_fields": [...]}` with
+/// the identity of the GPU and its [GpuStatus::background_fields], then the
+/// sample from [GpuStatus::compute_force] at startup, so clients can tell
+/// which fields the GPU supports. The following lines are live samples from
+/// [GpuStatus::compute], preceded by a new `device` line if the backend was
+/// rebuilt for another GPU.
+///
+/// When the GPU can't be read, the line is `{"error": "<message>"}` instead,
+/// with `"disconnected": "<PCI bus ID>"` if the GPU is [Disconnected], so
+/// that clients render their configured error output.
+///
+/// Each sample is passed to `on_sample` before it is published, for what
+/// should happen once per GPU rather than once per subscriber, such as
+/// recording it.
+///
+/// Nothing is polled while there are no subscribers. If `exit_when_unused`
+/// is set, the daemon exits once it had no subscribers for [UNUSED_GRACE].
+///
+/// Only one daemon runs per socket: the daemon holds a lock on a file next
+/// to it, and exits right away if another one already does.
+pub fn run(
+    gpu_status_handler: &dyn GpuStatus,
+    interval: Duration,
+    exit_when_unused: bool,
+    on_sample: &mut dyn FnMut(&mut GpuStatusData, &DeviceInfo),
+) -> Result<()> {
+    run_at(
+        &socket_path()?,
+        gpu_status_handler,
+        interval,
+        exit_when_unused,
+        on_sample,
+    )
+}
+
+fn run_at(
+    path: &Path,
+    gpu_status_handler: &dyn GpuStatus,
+    interval: Duration,
+    exit_when_unused: bool,
+    on_sample: &mut dyn FnMut(&mut GpuStatusData, &DeviceInfo),
+) -> Result<()> {
+    // Clients started together may each start a daemon. Without the lock,
+    // the second one would unlink the socket of the first.
+    let lock = File::create(path.with_extension("lock"))?;
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            eprintln!("A daemon is already listening on {}", path.display());
+            return Ok(());
+        }
+        Err(TryLockError::Error(e)) => return Err(e.into()),
+    }
+
+    // No daemon holds the lock, so the socket is left over from one that
+    // didn't shut down cleanly.
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+
+    let capabilities = sonic_rs::to_string(&gpu_status_handler.compute_force()?)?;
+    let subscribers = Arc::new(Mutex::new(Subscribers {
+        streams: Vec::new(),
+        device: device_line(gpu_status_handler)?,
+    }));
+
+    {
+        let subscribers = Arc::clone(&subscribers);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+
+                if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+                    continue;
+                }
+
+                // Holding the lock, so that the identity isn't replaced in
+                // between.
+                let mut subscribers = subscribers.lock().unwrap();
+                if writeln!(stream, "{}\n{capabilities}", subscribers.device).is_ok() {
+                    subscribers.streams.push(stream);
+                }
+            }
+        });
+    }
+
+    let mut last_used = Instant::now();
+
+    loop {
+        let has_subscribers = !subscribers.lock().unwrap().streams.is_empty();
+
+        if has_subscribers {
+            let message = match gpu_status_handler.compute() {
+                Ok(mut data) => {
+                    on_sample(&mut data, &gpu_status_handler.device_info());
+                    Message::Sample(Box::new(data))
+                }
+                Err(e) => Message::error(&e),
+            };
+            let mut line = sonic_rs::to_string(&message)?;
+
+            let device = device_line(gpu_status_handler)?;
+            let mut subscribers = subscribers.lock().unwrap();
+            if device != subscribers.device {
+                line = format!("{device}\n{line}");
+                subscribers.device = device;
+            }
+
+            subscribers
+                .streams
+                .retain_mut(|stream| writeln!(stream, "{line}").is_ok());
+
+            last_used = Instant::now();
+        } else if exit_when_unused && last_used.elapsed() > UNUSED_GRACE {
+            let _ = fs::remove_file(path);
+            return Ok(());
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+/// The streams of the subscribers, and the identity line new ones get.
+struct Subscribers {
+    streams: Vec<UnixStream>,
+    device: String,
+}
+
+fn device_line(gpu_status_handler: &dyn GpuStatus) -> Result<String> {
+    Ok(sonic_rs::to_string(&Message::Device {
+        device: (*gpu_status_handler.device_info()).clone(),
+        background_fields: (gpu_status_handler.background_fields().iter())
+            .map(ToString::to_string)
+            .collect(),
+    })?)
+}
+
+/// What the daemon last sent about its GPU.
+#[derive(Default)]
+struct Device {
+    info: Arc<DeviceInfo>,
+    background_fields: Vec<MemField>,
+}
+
+/// A [GpuStatus] that receives samples from a daemon instead of polling the
+/// GPU itself.
+///
+/// [GpuStatus::compute] blocks until the daemon publishes the next sample,
+/// so the daemon paces the output.
+pub struct Subscription {
+    path: PathBuf,
+    daemon_args: Vec<OsString>,
+    reader: RefCell<BufReader<UnixStream>>,
+    capabilities: GpuStatusData,
+    device: RefCell<Device>,
+}
+
+impl Subscription {
+    /// Subscribes to the daemon, starting one with `daemon_args` before the
+    /// `daemon` command if none is running.
+    pub fn connect(daemon_args: Vec<OsString>) -> Result<Self> {
+        let path = socket_path()?;
+        let mut device = Device::default();
+        let (reader, capabilities) = subscribe(&path, &daemon_args, &mut device)?;
+
+        Ok(Self {
+            path,
+            daemon_args,
+            reader: RefCell::new(reader),
+            capabilities,
+            device: RefCell::new(device),
+        })
+    }
+
+    fn read_sample(&self) -> Result<Option<GpuStatusData>> {
+        read_sample(&mut self.reader.borrow_mut(), &mut self.device.borrow_mut())
+    }
+}
+
+impl GpuStatus for Subscription {
+    fn compute(&self) -> Result<GpuStatusData> {
+        if let Some(data) = self.read_sample()? {
+            return Ok(data);
+        }
+
+        // The daemon went away, start a new one and carry on.
+        let (reader, _) = subscribe(&self.path, &self.daemon_args, &mut self.device.borrow_mut())?;
+        *self.reader.borrow_mut() = reader;
+
+        self.read_sample()?
+            .ok_or(eyre!("The daemon closed the connection"))
+    }
+
+    fn compute_force(&self) -> Result<GpuStatusData> {
+        Ok(self.capabilities.clone())
+    }
+
+    fn device_info(&self) -> Arc<DeviceInfo> {
+        Arc::clone(&self.device.borrow().info)
+    }
+
+    fn background_fields(&self) -> Vec<MemField> {
+        self.device.borrow().background_fields.clone()
+    }
+
+    fn paces_samples(&self) -> bool {
+        true
+    }
+
+    fn is_hardware(&self) -> bool {
+        false
+    }
+}
+
+fn subscribe(
+    path: &Path,
+    daemon_args: &[OsString],
+    device: &mut Device,
+) -> Result<(BufReader<UnixStream>, GpuStatusData)> {
+    let stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(_) => {
+            spawn_daemon(daemon_args)?;
+            connect_with_retry(path)?
+        }
+    };
+
+    let mut reader = BufReader::new(stream);
+    let capabilities =
+        read_sample(&mut reader, device)?.ok_or(eyre!("The daemon closed the connection"))?;
+
+    Ok((reader, capabilities))
+}
+
+fn connect_with_retry(path: &Path) -> Result<UnixStream> {
+    for _ in 0..CONNECT_ATTEMPTS {
+        thread::sleep(CONNECT_DELAY);
+
+        if let Ok(stream) = UnixStream::connect(path) {
+            return Ok(stream);
+        }
+    }
+
+    Err(eyre!(
+        "Unable to connect to the daemon at {}",
+        path.display()
+    ))
+}
+
+/// Returns `None` on end of stream, and the error of the daemon if it
+/// couldn't read the GPU. Identity lines before the sample replace
+/// `device`.
+fn read_sample(
+    reader: &mut BufReader<UnixStream>,
+    device: &mut Device,
+) -> Result<Option<GpuStatusData>> {
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        match sonic_rs::from_str(&line)? {
+            Message::Device {
+                device: info,
+                background_fields,
+            } => {
+                *device = Device {
+                    info: Arc::new(info),
+                    // Fields unknown to this version are left out.
+                    background_fields: (background_fields.iter())
+                        .filter_map(|field| field.parse().ok())
+                        .collect(),
+                }
+            }
+            Message::Sample(data) => return Ok(Some(*data)),
+            Message::Error {
+                disconnected: Some(pci_bus_id),
+                ..
+            } => return Err(Report::new(Disconnected { pci_bus_id })),
+            Message::Error { error, .. } => return Err(eyre!("{error}")),
+        }
+    }
+}
+
+/// Starts a daemon in its own process group, so it outlives the client when
+/// the status bar kills the client's group.
+fn spawn_daemon(daemon_args: &[OsString]) -> Result<()> {
+    let mut child = process::Command::new(env::current_exe()?)
+        .args(daemon_args)
+        .args(["daemon", "--exit-when-unused"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+
+    // Reap the daemon when it exits so it doesn't linger as a zombie.
+    thread::spawn(move || child.wait());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedStatus {
+        device_info: Arc<DeviceInfo>,
+    }
+
+    impl FixedStatus {
+        fn new() -> Self {
+            Self {
+                device_info: Arc::new(DeviceInfo {
+                    vendor: "nvidia".to_string(),
+                    pci_bus_id: Some("0000:01:00.0".to_string()),
+                    ..Default::default()
+                }),
+            }
+        }
+    }
+
+    impl GpuStatus for FixedStatus {
+        fn compute(&self) -> Result<GpuStatusData> {
+            Ok(GpuStatusData {
+                powered_on: true,
+                ..Default::default()
+            })
+        }
+
+        fn compute_force(&self) -> Result<GpuStatusData> {
+            Ok(GpuStatusData {
+                powered_on: true,
+                has_running_processes: true,
+                gpu_utilization: Some(100),
+                ..Default::default()
+            })
+        }
+
+        fn device_info(&self) -> Arc<DeviceInfo> {
+            Arc::clone(&self.device_info)
+        }
+
+        fn background_fields(&self) -> Vec<MemField> {
+            vec![MemField::Tx]
+        }
+    }
+
+    /// Fails to compute once started, as if the GPU was unplugged.
+    struct UnpluggedStatus;
+
+    impl GpuStatus for UnpluggedStatus {
+        fn compute(&self) -> Result<GpuStatusData> {
+            Err(Report::new(Disconnected {
+                pci_bus_id: "0000:01:00.0".to_string(),
+            }))
+        }
+
+        fn compute_force(&self) -> Result<GpuStatusData> {
+            Ok(GpuStatusData::default())
+        }
+
+        fn device_info(&self) -> Arc<DeviceInfo> {
+            Arc::default()
+        }
+    }
+
+    /// Test that a subscriber gets the identity and the capabilities first,
+    /// then live samples.
+    #[test]
+    fn test_publish_and_subscribe() {
+        let path = env::temp_dir().join(format!("gpu-usage-waybar-test-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+
+        {
+            let path = path.clone();
+            thread::spawn(move || {
+                run_at(
+                    &path,
+                    &FixedStatus::new(),
+                    Duration::from_millis(10),
+                    false,
+                    &mut |data, device| {
+                        assert_eq!(device.vendor, "nvidia");
+                        data.fan_speed = Some(30);
+                    },
+                )
+                .unwrap()
+            });
+        }
+
+        let stream = connect_with_retry(&path).unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut device = Device::default();
+
+        let capabilities = read_sample(&mut reader, &mut device).unwrap().unwrap();
+        assert_eq!(capabilities.gpu_utilization, Some(100));
+        assert_eq!(device.info, FixedStatus::new().device_info);
+        assert_eq!(device.background_fields, [MemField::Tx]);
+
+        let sample = read_sample(&mut reader, &mut device).unwrap().unwrap();
+        assert!(sample.powered_on);
+        assert_eq!(sample.gpu_utilization, None);
+        // Samples go through `on_sample` before they are published.
+        assert_eq!(sample.fan_speed, Some(30));
+
+        // A second daemon leaves the socket of the first alone.
+        run_at(
+            &path,
+            &FixedStatus::new(),
+            Duration::from_millis(10),
+            false,
+            &mut |_, _| {},
+        )
+        .unwrap();
+        assert!(read_sample(&mut reader, &mut device).unwrap().is_some());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("lock"));
+    }
+
+    /// Test that errors reach subscribers, keeping [Disconnected].
+    #[test]
+    fn test_publish_error() {
+        let path = env::temp_dir().join(format!(
+            "gpu-usage-waybar-test-error-{}.sock",
+            process::id()
+        ));
+        let _ = fs::remove_file(&path);
+
+        {
+            let path = path.clone();
+            thread::spawn(move || {
+                run_at(
+                    &path,
+                    &UnpluggedStatus,
+                    Duration::from_millis(10),
+                    false,
+                    &mut |_, _| {},
+                )
+                .unwrap()
+            });
+        }
+
+        let mut reader = BufReader::new(connect_with_retry(&path).unwrap());
+        let mut device = Device::default();
+        assert!(read_sample(&mut reader, &mut device).unwrap().is_some());
+
+        let Err(error) = read_sample(&mut reader, &mut device) else {
+            panic!("Expected an error");
+        };
+        assert_eq!(
+            error.downcast_ref::<Disconnected>().unwrap().pci_bus_id,
+            "0000:01:00.0"
+        );
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(path.with_extension("lock"));
+    }
+}
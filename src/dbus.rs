@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::{Result, WrapErr, eyre};
+use sonic_rs::{JsonContainerTrait, JsonValueTrait};
+use zbus::{
+    blocking::{Connection, connection::Builder},
+    interface,
+    zvariant::{OwnedValue, Value},
+};
+
+use crate::{
+    alerts::{AlertEvent, AlertEventKind},
+    gpu_status::GpuStatusData,
+};
+
+pub const BUS_NAME: &str = "io.github.PolpOnline.GpuUsageWaybar";
+pub const OBJECT_PATH: &str = "/io/github/PolpOnline/GpuUsageWaybar";
+
+#[derive(Default)]
+struct GpuService {
+    powered_on: bool,
+    has_running_processes: bool,
+    metrics: HashMap<String, OwnedValue>,
+}
+
+#[interface(name = "io.github.PolpOnline.GpuUsageWaybar1")]
+impl GpuService {
+    /// Whether the GPU is powered on at the PCI level.
+    #[zbus(property)]
+    fn powered_on(&self) -> bool {
+        self.powered_on
+    }
+
+    /// Whether any process is using the GPU.
+    #[zbus(property)]
+    fn has_running_processes(&self) -> bool {
+        self.has_running_processes
+    }
+
+    /// Available metrics keyed like the `raw` output, e.g. `power_watts`.
+    /// Unavailable metrics are left out.
+    #[zbus(property)]
+    fn metrics(&self) -> HashMap<String, OwnedValue> {
+        self.metrics
+            .iter()
+            .filter_map(|(k, v)| Some((k.clone(), v.try_clone().ok()?)))
+            .collect()
+    }
+}
+
+/// Publishes samples on the session bus.
+///
+/// The object at [OBJECT_PATH] exposes the latest sample as properties and
+/// emits `org.freedesktop.DBus.Properties.PropertiesChanged` when they change.
+pub struct DbusPublisher {
+    connection: Connection,
+}
+
+impl DbusPublisher {
+    pub fn new() -> Result<Self> {
+        Self::with_builder(Builder::session()?)
+    }
+
+    fn with_builder(builder: Builder) -> Result<Self> {
+        let connection = builder
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, GpuService::default())?
+            .build()
+            .wrap_err_with(|| {
+                format!("Unable to own {BUS_NAME}, is another instance running with `--dbus`?")
+            })?;
+
+        Ok(Self { connection })
+    }
+
+    pub fn publish(&self, data: &GpuStatusData) -> Result<()> {
+        let iface_ref = self
+            .connection
+            .object_server()
+            .interface::<_, GpuService>(OBJECT_PATH)?;
+        let emitter = iface_ref.signal_emitter();
+        let mut iface = iface_ref.get_mut();
+
+        if iface.powered_on != data.powered_on {
+            iface.powered_on = data.powered_on;
+            zbus::block_on(iface.powered_on_changed(emitter))?;
+        }
+
+        if iface.has_running_processes != data.has_running_processes {
+            iface.has_running_processes = data.has_running_processes;
+            zbus::block_on(iface.has_running_processes_changed(emitter))?;
+        }
+
+        let metrics = to_metrics(data)?;
+        if iface.metrics != metrics {
+            iface.metrics = metrics;
+            zbus::block_on(iface.metrics_changed(emitter))?;
+        }
+
+        Ok(())
+    }
+}
+
+const APP_NAME: &str = "gpu-usage-waybar";
+
+/// Sends alert notifications through `org.freedesktop.Notifications`.
+///
+/// A cleared alert replaces the notification sent when it fired.
+pub struct Notifier {
+    connection: Connection,
+    ids: HashMap<String, u32>,
+}
+
+impl Notifier {
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_connection(Connection::session()?))
+    }
+
+    fn with_connection(connection: Connection) -> Self {
+        Self {
+            connection,
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn notify(&mut self, event: &AlertEvent) -> Result<()> {
+        // Urgency levels from the Desktop Notifications Specification.
+        let (summary, urgency) = match event.kind {
+            AlertEventKind::Fired => (event.name.clone(), 2u8),
+            AlertEventKind::Cleared => (format!("{} cleared", event.name), 1u8),
+        };
+        let replaces_id = self.ids.get(&event.name).copied().unwrap_or(0);
+        let hints = HashMap::from([("urgency", Value::from(urgency))]);
+
+        let reply = self.connection.call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &(
+                APP_NAME,
+                replaces_id,
+                "",
+                summary,
+                event.message(),
+                Vec::<&str>::new(),
+                hints,
+                -1i32,
+            ),
+        )?;
+
+        let id: u32 = reply.body().deserialize()?;
+        self.ids.insert(event.name.clone(), id);
+
+        Ok(())
+    }
+}
+
+/// The D-Bus type of a metric, fixed for each key so that clients can rely on
+/// it whatever the value.
+#[derive(Clone, Copy)]
+enum MetricType {
+    /// `b`
+    Bool,
+    /// `t`
+    U64,
+    /// `d`
+    F64,
+    /// `s`
+    Str,
+}
+
+/// The keys of the `Metrics` property, as serialized in [GpuStatusData].
+const METRICS: &[(&str, MetricType)] = &[
+    ("has_running_processes", MetricType::Bool),
+    ("powered_on", MetricType::Bool),
+    ("gpu_utilization_percent", MetricType::U64),
+    ("mem_used_bytes", MetricType::U64),
+    ("mem_total_bytes", MetricType::U64),
+    ("gtt_used_bytes", MetricType::U64),
+    ("gtt_total_bytes", MetricType::U64),
+    ("vis_vram_used_bytes", MetricType::U64),
+    ("mem_rw_percent", MetricType::U64),
+    ("decoder_utilization_percent", MetricType::U64),
+    ("encoder_utilization_percent", MetricType::U64),
+    ("temperature_kelvin", MetricType::F64),
+    ("temperature_hotspot_kelvin", MetricType::F64),
+    ("temperature_mem_kelvin", MetricType::F64),
+    ("power_watts", MetricType::F64),
+    ("p_state", MetricType::Str),
+    ("p_level", MetricType::Str),
+    ("fan_speed_percent", MetricType::U64),
+    ("tx_bytes_per_second", MetricType::U64),
+    ("rx_bytes_per_second", MetricType::U64),
+    ("pcie_gen", MetricType::U64),
+    ("pcie_width", MetricType::U64),
+    ("pcie_max_gen", MetricType::U64),
+    ("pcie_max_width", MetricType::U64),
+    ("energy_joules", MetricType::F64),
+    ("cost", MetricType::F64),
+];
+
+/// Converts the non-null keys of the serialized `data` to D-Bus values of the
+/// type listed in [METRICS].
+fn to_metrics(data: &GpuStatusData) -> Result<HashMap<String, OwnedValue>> {
+    let value = sonic_rs::to_value(data)?;
+    let object = value
+        .as_object()
+        .ok_or(eyre!("GPU status did not serialize to an object"))?;
+
+    let mut metrics = HashMap::new();
+
+    for (key, metric_type) in METRICS {
+        let Some(value) = object.get(key) else {
+            continue;
+        };
+
+        let value = match metric_type {
+            MetricType::Bool => value.as_bool().map(Value::from),
+            MetricType::U64 => value.as_u64().map(Value::from),
+            MetricType::F64 => value.as_f64().map(Value::from),
+            MetricType::Str => value.as_str().map(|v| Value::from(v.to_string())),
+        };
+
+        if let Some(value) = value {
+            metrics.insert(key.to_string(), value.try_into()?);
+        }
+    }
+
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::{Arc, Mutex},
+    };
+
+    use amdgpu_sysfs::gpu_handle::PerformanceLevel;
+    use uom::si::{
+        energy::joule,
+        f32::{Energy, Information, Power},
+        information::mebibyte,
+        power::watt,
+        thermodynamic_temperature::kelvin,
+    };
+    use zbus::{blocking::fdo::PropertiesProxy, names::InterfaceName};
+
+    use super::*;
+    use crate::{
+        config::structs::Comparison,
+        gpu_status::{PState, Temperature},
+    };
+
+    const INTERFACE: InterfaceName<'static> =
+        InterfaceName::from_static_str_unchecked("io.github.PolpOnline.GpuUsageWaybar1");
+
+    /// Starts a private `dbus-daemon`, returning it with its address.
+    fn private_bus() -> Option<(Child, String)> {
+        let Ok(mut bus) = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            eprintln!("dbus-daemon not found, skipping");
+            return None;
+        };
+
+        let mut address = String::new();
+        BufReader::new(bus.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        Some((bus, address.trim().to_string()))
+    }
+
+    /// Test the published properties and change signals against a private
+    /// `dbus-daemon`. Skipped if `dbus-daemon` is not installed.
+    #[test]
+    fn test_publish_on_private_bus() {
+        let Some((mut bus, address)) = private_bus() else {
+            return;
+        };
+        let address = address.as_str();
+
+        let publisher = DbusPublisher::with_builder(Builder::address(address).unwrap()).unwrap();
+
+        let client = Builder::address(address).unwrap().build().unwrap();
+        let proxy = PropertiesProxy::builder(&client)
+            .destination(BUS_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut changes = proxy.receive_properties_changed().unwrap();
+
+        publisher
+            .publish(&GpuStatusData {
+                powered_on: true,
+                gpu_utilization: Some(42),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let signal = changes.next().unwrap();
+        let args = signal.args().unwrap();
+        assert_eq!(args.interface_name, INTERFACE);
+        assert_eq!(
+            args.changed_properties.get("PoweredOn"),
+            Some(&Value::from(true))
+        );
+
+        assert_eq!(
+            proxy.get(INTERFACE, "PoweredOn").unwrap(),
+            OwnedValue::from(true)
+        );
+
+        let metrics: HashMap<String, OwnedValue> =
+            proxy.get(INTERFACE, "Metrics").unwrap().try_into().unwrap();
+        assert_eq!(
+            metrics.get("gpu_utilization_percent"),
+            Some(&OwnedValue::from(42u64))
+        );
+        assert!(!metrics.contains_key("power_watts"));
+
+        bus.kill().unwrap();
+        bus.wait().unwrap();
+    }
+
+    /// Test that every key gets the type listed in [METRICS], including
+    /// floats that happen to be whole numbers.
+    #[test]
+    fn test_metric_types() {
+        let mib = Some(Information::new::<mebibyte>(512.0));
+        let temperature = Some(Temperature::new::<kelvin>(330.0));
+        let data = GpuStatusData {
+            has_running_processes: true,
+            powered_on: true,
+            gpu_utilization: Some(42),
+            mem_used: mib,
+            mem_total: mib,
+            gtt_used: mib,
+            gtt_total: mib,
+            vis_vram_used: mib,
+            mem_rw: Some(10),
+            decoder_utilization: Some(1),
+            encoder_utilization: Some(2),
+            temperature,
+            temperature_hotspot: temperature,
+            temperature_mem: temperature,
+            power: Some(Power::new::<watt>(100.0)),
+            p_state: Some(PState::P0),
+            p_level: Some(PerformanceLevel::Auto),
+            fan_speed: Some(30),
+            tx: mib,
+            rx: mib,
+            pcie_gen: Some(4),
+            pcie_width: Some(16),
+            pcie_max_gen: Some(4),
+            pcie_max_width: Some(16),
+            energy: Some(Energy::new::<joule>(3600.0)),
+            cost: Some(1.0),
+            ..Default::default()
+        };
+
+        let metrics = to_metrics(&data).unwrap();
+        assert_eq!(metrics.len(), METRICS.len());
+
+        for (key, metric_type) in METRICS {
+            let signature = match metric_type {
+                MetricType::Bool => "b",
+                MetricType::U64 => "t",
+                MetricType::F64 => "d",
+                MetricType::Str => "s",
+            };
+            assert_eq!(metrics[*key].value_signature(), signature, "{key}");
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeNotifications {
+        received: Arc<Mutex<Vec<(u32, String, String)>>>,
+    }
+
+    #[interface(name = "org.freedesktop.Notifications")]
+    impl FakeNotifications {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            _app_name: String,
+            replaces_id: u32,
+            _app_icon: String,
+            summary: String,
+            body: String,
+            _actions: Vec<String>,
+            _hints: HashMap<String, OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            self.received
+                .lock()
+                .unwrap()
+                .push((replaces_id, summary, body));
+            7
+        }
+    }
+
+    /// Test that a cleared alert replaces the notification of the fired one.
+    #[test]
+    fn test_notify_on_private_bus() {
+        let Some((mut bus, address)) = private_bus() else {
+            return;
+        };
+
+        let server = FakeNotifications::default();
+        let received = Arc::clone(&server.received);
+        let _server = Builder::address(address.as_str())
+            .unwrap()
+            .name("org.freedesktop.Notifications")
+            .unwrap()
+            .serve_at("/org/freedesktop/Notifications", server)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut notifier =
+            Notifier::with_connection(Builder::address(address.as_str()).unwrap().build().unwrap());
+
+        let mut event = AlertEvent {
+            kind: AlertEventKind::Fired,
+            name: "Hot".to_string(),
+            field: "temperature:c".to_string(),
+            value: Some(91.0),
+            comparison: Comparison::AtLeast,
+            threshold: 90.0,
+            hook: None,
+            notify: true,
+        };
+        notifier.notify(&event).unwrap();
+
+        event.kind = AlertEventKind::Cleared;
+        event.value = Some(80.0);
+        notifier.notify(&event).unwrap();
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                (
+                    0,
+                    "Hot".to_string(),
+                    "temperature:c is 91 (>= 90)".to_string()
+                ),
+                (
+                    7,
+                    "Hot cleared".to_string(),
+                    "temperature:c is back to 80".to_string()
+                ),
+            ]
+        );
+
+        bus.kill().unwrap();
+        bus.wait().unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+use std::time::Instant;
+
+use chrono::{Local, NaiveDate};
+use uom::si::{
+    energy::{joule, kilowatt_hour, microjoule},
+    f32::{Energy, Power, Time},
+    f64,
+    time::second,
+};
+
+use crate::{
+    config::structs::{EnergyConfig, EnergySince},
+    gpu_status::GpuStatusData,
+};
+
+/// What a sample tells about energy use.
+struct Reading {
+    at: Instant,
+    power: Option<Power>,
+    /// In microjoules.
+    counter: Option<u64>,
+}
+
+impl Reading {
+    /// Returns the energy used between `previous` and this reading.
+    fn used_since(&self, previous: &Reading) -> Energy {
+        match (previous.counter, self.counter) {
+            // The counter restarts when the driver is reloaded.
+            (Some(previous), Some(current)) if current >= previous => {
+                Energy::new::<microjoule>((current - previous) as f32)
+            }
+            _ => match (previous.power, self.power) {
+                (Some(previous_power), Some(power)) => {
+                    let seconds = self.at.duration_since(previous.at).as_secs_f32();
+                    (previous_power + power) / 2.0 * Time::new::<second>(seconds)
+                }
+                _ => Energy::new::<joule>(0.0),
+            },
+        }
+    }
+}
+
+/// Adds up the energy used by the GPU across samples for the `{energy}` and
+/// `{cost}` fields.
+///
+/// The energy counter of the backend is used when it has been read before,
+/// otherwise the power readings are integrated. The last counter reading is
+/// kept across samples without one, e.g. while an NVIDIA GPU is idle, so
+/// that the energy used in between is counted once the GPU is active again.
+/// Without a counter, time in which the power isn't known counts as no
+/// energy used.
+pub struct EnergyMeter {
+    since: EnergySince,
+    price: Option<f32>,
+    /// A float of double precision, so that the increments still add up
+    /// after weeks of uptime.
+    total: f64::Energy,
+    /// The day `total` started on, for [EnergySince::Midnight].
+    day: Option<NaiveDate>,
+    last: Option<Reading>,
+}
+
+impl EnergyMeter {
+    pub fn new(config: &EnergyConfig) -> Self {
+        Self {
+            since: config.since,
+            price: config.price,
+            total: f64::Energy::new::<joule>(0.0),
+            day: None,
+            last: None,
+        }
+    }
+
+    /// Adds the energy used since the previous sample and sets the `energy`
+    /// and `cost` of `data`, sampled at `at` on `today`.
+    pub fn update(&mut self, data: &mut GpuStatusData, at: Instant, today: NaiveDate) {
+        if self.since == EnergySince::Midnight && self.day != Some(today) {
+            self.total = f64::Energy::new::<joule>(0.0);
+        }
+        self.day = Some(today);
+
+        let reading = Reading {
+            at,
+            power: data.power,
+            counter: data
+                .energy_counter
+                .or_else(|| self.last.as_ref().and_then(|last| last.counter)),
+        };
+        if let Some(ref last) = self.last {
+            self.total += f64::Energy::new::<joule>(reading.used_since(last).get::<joule>().into());
+        }
+        self.last = Some(reading);
+
+        let total = Energy::new::<joule>(self.total.get::<joule>() as f32);
+        data.energy = Some(total);
+        data.cost = self.price.map(|price| total.get::<kilowatt_hour>() * price);
+    }
+
+    /// Same as [EnergyMeter::update] for a sample taken now.
+    pub fn update_now(&mut self, data: &mut GpuStatusData) {
+        self.update(data, Instant::now(), Local::now().date_naive());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uom::si::{energy::watt_hour, power::watt};
+
+    use super::*;
+
+    fn sample(watts: Option<f32>, counter_wh: Option<u64>) -> GpuStatusData {
+        GpuStatusData {
+            power: watts.map(Power::new::<watt>),
+            // 1 Wh is 3600 J.
+            energy_counter: counter_wh.map(|wh| wh * 3_600_000_000),
+            ..Default::default()
+        }
+    }
+
+    fn meter(since: EnergySince, price: Option<f32>) -> EnergyMeter {
+        EnergyMeter::new(&EnergyConfig { since, price })
+    }
+
+    #[test]
+    fn test_integrates_power() {
+        let mut meter = meter(EnergySince::Start, Some(0.5));
+        let start = Instant::now();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let hour = Duration::from_secs(3600);
+
+        let mut data = sample(Some(100.0), None);
+        meter.update(&mut data, start, today);
+        assert_eq!(data.energy.map(|e| e.get::<watt_hour>()), Some(0.0));
+
+        let mut data = sample(Some(300.0), None);
+        meter.update(&mut data, start + hour, today);
+        assert_eq!(data.energy.map(|e| e.get::<watt_hour>()), Some(200.0));
+        assert_eq!(data.cost, Some(0.1));
+
+        // An unknown power reading adds nothing.
+        let mut data = sample(None, None);
+        meter.update(&mut data, start + hour * 2, today);
+        assert_eq!(data.energy.map(|e| e.get::<watt_hour>()), Some(200.0));
+    }
+
+    #[test]
+    fn test_prefers_counter() {
+        let mut meter = meter(EnergySince::Start, None);
+        let start = Instant::now();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let hour = Duration::from_secs(3600);
+
+        meter.update(&mut sample(Some(100.0), Some(1000)), start, today);
+
+        let mut data = sample(Some(100.0), Some(1250));
+        meter.update(&mut data, start + hour, today);
+        assert_eq!(data.energy.map(|e| e.get::<watt_hour>()), Some(250.0));
+        assert_eq!(data.cost, None);
+
+        // The counter went back after a driver reload.
+        let mut data = sample(Some(100.0), Some(10));
+        meter.update(&mut data, start + hour * 2, today);
+        assert_eq!(data.energy.map(|e| e.get::<watt_hour>()), Some(350.0));
+    }
+
+    /// Test that the counter covers the samples in which it isn't read.
+    #[test]
+    fn test_counter_across_idle() {
+        let mut meter = meter(EnergySince::Start, None);
+        let start = Instant::now();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let hour = Duration::from_secs(3600);
+
+        meter.update(&mut sample(Some(100.0), Some(1000)), start, today);
+
+        // The GPU is idle and neither the power nor the counter is read.
+        let mut data = sample(None, None);
+        meter.update(&mut data, start + hour, today);
+        assert_eq!(data.energy.map(|e| e.get::<watt_hour>()), Some(0.0));
+
+        let mut data = sample(Some(100.0), Some(1250));
+        meter.update(&mut data, start + hour * 2, today);
+        assert_eq!(data.energy.map(|e| e.get::<watt_hour>()), Some(250.0));
+    }
+
+    /// Test that small increments of a large counter aren't rounded away.
+    #[test]
+    fn test_large_counter() {
+        let mut meter = meter(EnergySince::Start, None);
+        let start = Instant::now();
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let a_second = Duration::from_secs(1);
+        // About a year of counting at 30 W.
+        let counter = 10u64.pow(15);
+
+        let mut data = GpuStatusData {
+            energy_counter: Some(counter),
+            ..Default::default()
+        };
+        meter.update(&mut data, start, today);
+
+        data.energy_counter = Some(counter + 30_000_000);
+        meter.update(&mut data, start + a_second, today);
+        assert_eq!(data.energy.map(|e| e.get::<joule>()), Some(30.0));
+    }
+
+    #[test]
+    fn test_resets_at_midnight() {
+        let mut meter = meter(EnergySince::Midnight, None);
+        let start = Instant::now();
+        let hour = Duration::from_secs(3600);
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        let tomorrow = today.succ_opt().unwrap();
+
+        meter.update(&mut sample(Some(100.0), None), start, today);
+        meter.update(&mut sample(Some(100.0), None), start + hour, today);
+
+        let mut data = sample(Some(100.0), None);
+        meter.update(&mut data, start + hour * 2, tomorrow);
+        assert_eq!(data.energy.map(|e| e.get::<watt_hour>()), Some(100.0));
+    }
+}
@@ -0,0 +1,290 @@
+//! Per-process GPU usage from DRM fdinfo.
+//!
+//! Every open DRM file descriptor has a `/proc/<pid>/fdinfo/<fd>` entry with
+//! `drm-*` keys, see
+//! <https://www.kernel.org/doc/html/latest/gpu/drm-usage-stats.html>.
+//! A client may be shared between processes and file descriptors, so clients
+//! are counted once by `drm-client-id`.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, hash_map::Entry},
+    fs,
+    time::Instant,
+};
+
+use uom::si::{f32::Information, information::byte};
+
+use crate::{fs_root::FsRoot, gpu_status::GpuProcess, proc_cache::ProcessCache};
+
+/// The usage of one DRM client as read from fdinfo.
+#[derive(Debug, Default, PartialEq)]
+struct ClientUsage {
+    id: u64,
+    /// `drm-memory-vram` in bytes.
+    vram: Option<u64>,
+    /// `drm-engine-gfx`, the nanoseconds the client kept the graphics
+    /// engine busy since it was opened.
+    gfx_ns: Option<u64>,
+}
+
+/// Parses an fdinfo file, returning the client if it belongs to `driver`
+/// on the device at `pci_bus_id`.
+fn parse_fdinfo(content: &str, driver: &str, pci_bus_id: &str) -> Option<ClientUsage> {
+    let mut client = ClientUsage::default();
+    let (mut has_driver, mut has_device, mut has_id) = (false, false, false);
+
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match key {
+            "drm-driver" => has_driver = value == driver,
+            "drm-pdev" => has_device = value.eq_ignore_ascii_case(pci_bus_id),
+            "drm-client-id" => {
+                client.id = value.parse().ok()?;
+                has_id = true;
+            }
+            "drm-memory-vram" => client.vram = parse_memory(value),
+            "drm-engine-gfx" => {
+                client.gfx_ns = value.strip_suffix(" ns").and_then(|ns| ns.parse().ok());
+            }
+            _ => {}
+        }
+    }
+
+    (has_driver && has_device && has_id).then_some(client)
+}
+
+/// Parses a `drm-memory-*` value such as `1024 KiB`.
+fn parse_memory(value: &str) -> Option<u64> {
+    let (number, unit) = value.split_once(' ').unwrap_or((value, ""));
+    let multiplier = match unit {
+        "" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        _ => return None,
+    };
+
+    Some(number.parse::<u64>().ok()? * multiplier)
+}
+
+/// Tracks the DRM clients of one device between polls to turn their
+/// cumulative engine time into a utilization.
+pub struct DrmClients {
+    driver: &'static str,
+    pci_bus_id: String,
+    root: FsRoot,
+    cache: RefCell<ProcessCache>,
+    /// The engine time of every client seen in the last poll, and when.
+    last: RefCell<(Option<Instant>, HashMap<u64, u64>)>,
+}
+
+impl DrmClients {
+    pub fn new(driver: &'static str, pci_bus_id: &str, root: FsRoot) -> Self {
+        Self {
+            driver,
+            pci_bus_id: pci_bus_id.to_string(),
+            root,
+            cache: RefCell::default(),
+            last: RefCell::default(),
+        }
+    }
+
+    /// Returns the processes using the device with their VRAM and the share
+    /// of the graphics engine they used since the previous call.
+    ///
+    /// The utilization is `None` on the first call and for new clients.
+    pub fn processes(&self) -> Option<Vec<GpuProcess>> {
+        self.processes_at(Instant::now())
+    }
+
+    fn processes_at(&self, now: Instant) -> Option<Vec<GpuProcess>> {
+        // Processes using the device are scanned on every poll, so their
+        // clients are kept from the scan rather than read again.
+        let mut clients_by_pid = HashMap::new();
+        let users = self
+            .cache
+            .borrow_mut()
+            .scan(&self.root, |proc| {
+                let clients = self.clients(proc.pid);
+                let uses_gpu = !clients.is_empty();
+                if uses_gpu {
+                    clients_by_pid.insert(proc.pid, clients);
+                }
+                uses_gpu
+            })
+            .ok()?;
+
+        let mut last = self.last.borrow_mut();
+        let (last_poll, last_engine) = &mut *last;
+        let elapsed_ns = last_poll.map(|t| now.duration_since(t).as_nanos() as f32);
+
+        let mut seen = HashMap::new();
+        let mut processes = Vec::new();
+
+        for (pid, name) in users {
+            let mut has_client = false;
+            let mut vram = None;
+            let mut busy_ns = None;
+
+            for client in clients_by_pid.remove(&pid).unwrap_or_default() {
+                // Only the first file descriptor of a client counts.
+                let Entry::Vacant(slot) = seen.entry(client.id) else {
+                    continue;
+                };
+                slot.insert(client.gfx_ns.unwrap_or_default());
+                has_client = true;
+
+                if let Some(bytes) = client.vram {
+                    *vram.get_or_insert(0) += bytes;
+                }
+                if let (Some(gfx_ns), Some(&last_ns)) = (client.gfx_ns, last_engine.get(&client.id))
+                {
+                    *busy_ns.get_or_insert(0) += gfx_ns.saturating_sub(last_ns);
+                }
+            }
+
+            if has_client {
+                processes.push(GpuProcess {
+                    pid: pid as u32,
+                    name,
+                    vram: vram.map(|bytes| Information::new::<byte>(bytes as f32)),
+                    gpu_utilization: busy_ns.zip(elapsed_ns).map(|(busy, elapsed)| {
+                        (busy as f32 / elapsed * 100.0).round().clamp(0.0, 100.0) as u8
+                    }),
+                });
+            }
+        }
+
+        *last_poll = Some(now);
+        *last_engine = seen;
+
+        Some(processes)
+    }
+
+    /// Returns the clients of the device open in process `pid`, one per file
+    /// descriptor.
+    fn clients(&self, pid: i32) -> Vec<ClientUsage> {
+        let Ok(entries) = fs::read_dir(self.root.proc().join(pid.to_string()).join("fdinfo"))
+        else {
+            return Vec::new();
+        };
+
+        entries
+            .flatten()
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|content| parse_fdinfo(&content, self.driver, &self.pci_bus_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use uom::si::information::mebibyte;
+
+    use super::*;
+    use crate::fs_root::{temp_dir, write_stat};
+
+    const BUS_ID: &str = "0000:03:00.0";
+
+    fn fdinfo(client_id: u64, vram_kib: u64, gfx_ns: u64) -> String {
+        format!(
+            "pos:\t0\nflags:\t02100002\ndrm-driver:\tamdgpu\ndrm-client-id:\t{client_id}\n\
+             drm-pdev:\t{BUS_ID}\ndrm-memory-vram:\t{vram_kib} KiB\ndrm-engine-gfx:\t{gfx_ns} ns\n"
+        )
+    }
+
+    fn write_process(root: &Path, pid: i32, name: &str, fds: &[(u32, String)]) {
+        write_stat(root, pid, name, 100);
+
+        let dir = root.join("proc").join(pid.to_string());
+        fs::create_dir_all(dir.join("fdinfo")).unwrap();
+        for (fd, content) in fds {
+            fs::write(dir.join("fdinfo").join(fd.to_string()), content).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_fdinfo() {
+        assert_eq!(
+            parse_fdinfo(&fdinfo(7, 2048, 100), "amdgpu", BUS_ID),
+            Some(ClientUsage {
+                id: 7,
+                vram: Some(2 << 20),
+                gfx_ns: Some(100),
+            })
+        );
+        assert_eq!(
+            parse_fdinfo(&fdinfo(7, 0, 0), "amdgpu", "0000:0a:00.0"),
+            None
+        );
+        assert_eq!(parse_fdinfo(&fdinfo(7, 0, 0), "i915", BUS_ID), None);
+        // Regular files have fdinfo too.
+        assert_eq!(
+            parse_fdinfo("pos:\t0\nflags:\t0100000\n", "amdgpu", BUS_ID),
+            None
+        );
+    }
+
+    /// Test that engine time is diffed between polls, and that a client
+    /// open through several file descriptors is counted once.
+    #[test]
+    fn test_processes() {
+        let dir = temp_dir("fdinfo");
+
+        write_process(
+            &dir,
+            1000,
+            "firefox",
+            &[(5, fdinfo(7, 1024, 0)), (6, fdinfo(7, 1024, 0))],
+        );
+        write_process(
+            &dir,
+            1001,
+            "bash",
+            &[(0, "pos:\t0\nflags:\t02\n".to_string())],
+        );
+
+        let clients = DrmClients::new("amdgpu", BUS_ID, FsRoot::new(dir.clone()));
+        let start = Instant::now();
+
+        let processes = clients.processes_at(start).unwrap();
+        assert_eq!(
+            processes,
+            [GpuProcess {
+                pid: 1000,
+                name: "firefox".to_string(),
+                vram: Some(Information::new::<mebibyte>(1.0)),
+                gpu_utilization: None,
+            }]
+        );
+
+        // 250 ms of 1 s busy.
+        write_process(
+            &dir,
+            1000,
+            "firefox",
+            &[
+                (5, fdinfo(7, 1024, 250_000_000)),
+                (6, fdinfo(7, 1024, 250_000_000)),
+            ],
+        );
+        write_process(&dir, 1002, "mpv", &[(4, fdinfo(9, 512, 0))]);
+
+        let processes = clients
+            .processes_at(start + Duration::from_secs(1))
+            .unwrap();
+        let utilization: Vec<_> = processes
+            .iter()
+            .map(|p| (p.pid, p.gpu_utilization))
+            .collect();
+        assert_eq!(utilization, [(1000, Some(25)), (1002, None)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,7 +3,7 @@ use std::{
     fmt::{Debug, Display},
     str::FromStr,
 };
-use strum::{Display, EnumString};
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator, VariantNames};
 
 use crate::formatter::{FormatSegments, units::*};
 
@@ -16,6 +16,7 @@ pub enum Field {
         precision: Option<usize>,
     },
     Temperature {
+        field: TemperatureField,
         unit: TemperatureUnit,
         precision: Option<usize>,
     },
@@ -23,6 +24,16 @@ pub enum Field {
         unit: PowerUnit,
         precision: Option<usize>,
     },
+    Energy {
+        unit: EnergyUnit,
+        precision: Option<usize>,
+    },
+    /// `{cost}`, always with two decimal places.
+    Cost,
+    /// `{processes}`, or `{top_processes:N}` for the first `N` only.
+    Processes {
+        limit: Option<usize>,
+    },
     Unknown,
 }
 
@@ -50,15 +61,24 @@ impl TryFrom<FormatSegments<'_>> for Field {
         }
 
         let field = match segments.field {
-            "temperature" => {
-                let (unit, precision) =
-                    parse_unit_and_precision!(TemperatureUnit, UnitParseError::Temperature);
-                Field::Temperature { unit, precision }
-            }
             "power" => {
                 let (unit, precision) = parse_unit_and_precision!(PowerUnit, UnitParseError::Power);
                 Field::Power { unit, precision }
             }
+            "energy" => {
+                let (unit, precision) =
+                    parse_unit_and_precision!(EnergyUnit, UnitParseError::Energy);
+                Field::Energy { unit, precision }
+            }
+            "cost" => Field::Cost,
+            "processes" => Field::Processes { limit: None },
+            "top_processes" => {
+                let limit = segments.unit.ok_or(UnitParseError::NoUnit)?;
+                let limit = limit
+                    .parse()
+                    .map_err(|_| UnitParseError::Limit(limit.to_string()))?;
+                Field::Processes { limit: Some(limit) }
+            }
             field_name => {
                 if let Ok(f) = MemField::from_str(field_name) {
                     let (unit, precision) =
@@ -69,6 +89,15 @@ impl TryFrom<FormatSegments<'_>> for Field {
                         unit,
                         precision,
                     }
+                } else if let Ok(f) = TemperatureField::from_str(field_name) {
+                    let (unit, precision) =
+                        parse_unit_and_precision!(TemperatureUnit, UnitParseError::Temperature);
+
+                    Field::Temperature {
+                        field: f,
+                        unit,
+                        precision,
+                    }
                 } else {
                     SimpleField::from_str(field_name)
                         .map(Field::Simple)
@@ -81,7 +110,78 @@ impl TryFrom<FormatSegments<'_>> for Field {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString)]
+impl Field {
+    /// Returns every known field as `(name, accepted units, field)`.
+    ///
+    /// Fields with configurable units are built with a default unit (`MiB`,
+    /// `c`, `w` or `wh`) and no precision. Unitless fields have an empty unit list.
+    pub fn catalog() -> Vec<(String, &'static [&'static str], Field)> {
+        let simple = SimpleField::iter().map(|f| (f.to_string(), &[][..], Field::Simple(f)));
+
+        let mem = MemField::iter().map(|f| {
+            let field = Field::Mem {
+                field: f,
+                unit: MemUnit::MiB,
+                precision: None,
+            };
+            (f.to_string(), MemUnit::VARIANTS, field)
+        });
+
+        let temperature = TemperatureField::iter().map(|f| {
+            let field = Field::Temperature {
+                field: f,
+                unit: TemperatureUnit::Celsius,
+                precision: None,
+            };
+            (f.to_string(), TemperatureUnit::VARIANTS, field)
+        });
+
+        let power = (
+            "power".to_string(),
+            PowerUnit::VARIANTS,
+            Field::Power {
+                unit: PowerUnit::Watt,
+                precision: None,
+            },
+        );
+
+        let energy = (
+            "energy".to_string(),
+            EnergyUnit::VARIANTS,
+            Field::Energy {
+                unit: EnergyUnit::WattHour,
+                precision: None,
+            },
+        );
+
+        let cost = ("cost".to_string(), &[][..], Field::Cost);
+
+        let processes = (
+            "processes".to_string(),
+            &[][..],
+            Field::Processes { limit: None },
+        );
+
+        simple
+            .chain(mem)
+            .chain(temperature)
+            .chain([power, energy, cost, processes])
+            .collect()
+    }
+
+    /// Returns the unit this field is rendered in, if it has one.
+    pub fn unit_name(&self) -> Option<String> {
+        match self {
+            Field::Mem { unit, .. } => Some(unit.to_string()),
+            Field::Temperature { unit, .. } => Some(unit.to_string()),
+            Field::Power { unit, .. } => Some(unit.to_string()),
+            Field::Energy { unit, .. } => Some(unit.to_string()),
+            Field::Simple(_) | Field::Cost | Field::Processes { .. } | Field::Unknown => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, EnumIter)]
 #[strum(serialize_all = "snake_case")]
 pub enum SimpleField {
     GpuUtilization,
@@ -92,15 +192,51 @@ pub enum SimpleField {
     PState,
     PLevel,
     FanSpeed,
+    PcieGen,
+    PcieWidth,
+    PcieMaxGen,
+    PcieMaxWidth,
+    GpuName,
+    Vendor,
+    DriverVersion,
+    VbiosVersion,
+    PciBusId,
+    Uuid,
+}
+
+impl SimpleField {
+    /// Whether the field is text describing the device rather than a reading.
+    pub fn is_text(self) -> bool {
+        matches!(
+            self,
+            SimpleField::GpuName
+                | SimpleField::Vendor
+                | SimpleField::DriverVersion
+                | SimpleField::VbiosVersion
+                | SimpleField::PciBusId
+                | SimpleField::Uuid
+        )
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, EnumIter)]
 #[strum(serialize_all = "snake_case")]
 pub enum MemField {
     MemUsed,
     MemTotal,
     Tx,
     Rx,
+    GttUsed,
+    GttTotal,
+    VisVramUsed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, EnumIter)]
+#[strum(serialize_all = "snake_case")]
+pub enum TemperatureField {
+    Temperature,
+    TemperatureHotspot,
+    TemperatureMem,
 }
 
 #[derive(Debug)]
@@ -115,6 +251,12 @@ pub enum UnitParseError {
     Temperature(String),
     /// Error parsing power unit.
     Power(String),
+    /// Error parsing energy unit.
+    Energy(String),
+    /// Unknown field or malformed placeholder.
+    Field(String),
+    /// Error parsing the number of processes.
+    Limit(String),
 }
 
 impl Display for UnitParseError {
@@ -125,6 +267,9 @@ impl Display for UnitParseError {
             UnitParseError::Memory(unit) => write!(f, "Invalid memory unit: `{unit}`"),
             UnitParseError::Temperature(unit) => write!(f, "Invalid temperature unit: `{unit}`"),
             UnitParseError::Power(unit) => write!(f, "Invalid power unit: `{unit}`"),
+            UnitParseError::Energy(unit) => write!(f, "Invalid energy unit: `{unit}`"),
+            UnitParseError::Field(field) => write!(f, "Invalid field: `{field}`"),
+            UnitParseError::Limit(limit) => write!(f, "Invalid number of processes: `{limit}`"),
         }
     }
 }
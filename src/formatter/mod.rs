@@ -6,7 +6,7 @@ use std::fmt::Debug;
 
 use crate::{
     formatter::fields::*,
-    gpu_status::{GpuStatusData, WriteFieldError},
+    gpu_status::{DeviceInfo, GpuStatusData, WriteFieldError},
 };
 
 #[derive(Debug, PartialEq)]
@@ -21,11 +21,12 @@ pub struct State {
 }
 
 impl State {
-    /// Assembles `self.chunks` into `self.buffer` using the provided `data`.
+    /// Assembles `self.chunks` into `self.buffer` using the provided `data`
+    /// and `device`.
     ///
     /// Writes `"N/A"` if a variable segment in `chunks` is [`Field::Unknown`],
     /// or if the corresponding field in `data` is `None`.
-    pub fn assemble(&mut self, data: &GpuStatusData) {
+    pub fn assemble(&mut self, data: &GpuStatusData, device: &DeviceInfo) {
         self.buffer.clear();
 
         for chunk in &self.chunks {
@@ -34,7 +35,7 @@ impl State {
                 Chunk::Variable(field) => {
                     if matches!(
                         // write_field() writes "N/A" if field is Field::Unknown.
-                        data.write_field(*field, &mut self.buffer),
+                        data.write_field(device, *field, &mut self.buffer),
                         Err(WriteFieldError::FieldIsNone)
                     ) {
                         self.buffer.push_str("N/A");
@@ -72,6 +73,24 @@ impl<'a> FormatSegments<'a> {
     }
 }
 
+/// Parses a single placeholder given without braces, e.g. `temperature:c.1`.
+///
+/// Unlike format strings, unknown fields are an error.
+pub fn parse_field(placeholder: &str) -> Result<Field, UnitParseError> {
+    let wrapped = format!("{{{placeholder}}}");
+    let invalid = || UnitParseError::Field(placeholder.to_string());
+
+    let caps = get_regex()
+        .captures(&wrapped)
+        .filter(|caps| caps[0].len() == wrapped.len())
+        .ok_or_else(invalid)?;
+
+    match Field::try_from(FormatSegments::from_caps_unchecked(&caps))? {
+        Field::Unknown => Err(invalid()),
+        field => Ok(field),
+    }
+}
+
 pub fn get_regex() -> Regex {
     Regex::new(r"\{(\w+)(?::(\w+)(?:\.(\d+))?)?\}").unwrap()
 }
@@ -183,6 +202,7 @@ RX: {rx:MiB.2} MiB/s";
         assert!(matches!(
             field,
             Chunk::Variable(Field::Temperature {
+                field: TemperatureField::Temperature,
                 unit: TemperatureUnit::Celsius,
                 precision: None
             })
@@ -192,10 +212,73 @@ RX: {rx:MiB.2} MiB/s";
         assert!(matches!(
             field,
             Chunk::Variable(Field::Temperature {
+                field: TemperatureField::Temperature,
                 unit: TemperatureUnit::Celsius,
                 precision: Some(2)
             })
         ));
+
+        let field = &parse("{temperature_hotspot:f}").unwrap()[0];
+        assert!(matches!(
+            field,
+            Chunk::Variable(Field::Temperature {
+                field: TemperatureField::TemperatureHotspot,
+                unit: TemperatureUnit::Fahrenheit,
+                precision: None
+            })
+        ));
+    }
+
+    /// Test that every catalogued field parses back from its placeholder.
+    #[test]
+    fn test_catalog_round_trip() {
+        for (name, units, field) in Field::catalog() {
+            let placeholder = match field.unit_name() {
+                Some(unit) => {
+                    assert!(units.contains(&unit.as_str()));
+                    format!("{{{name}:{unit}}}")
+                }
+                None => format!("{{{name}}}"),
+            };
+
+            assert_eq!(parse(&placeholder).unwrap()[0], Chunk::Variable(field));
+        }
+    }
+
+    #[test]
+    fn test_parse_field() {
+        assert_eq!(
+            parse_field("mem_used:GiB.1").unwrap(),
+            Field::Mem {
+                field: MemField::MemUsed,
+                unit: MemUnit::GiB,
+                precision: Some(1),
+            }
+        );
+        assert!(matches!(
+            parse_field("not_a_field"),
+            Err(UnitParseError::Field(_))
+        ));
+        assert!(matches!(
+            parse_field("fan_speed} {gpu_utilization"),
+            Err(UnitParseError::Field(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_processes() {
+        assert_eq!(
+            parse_field("top_processes:3").unwrap(),
+            Field::Processes { limit: Some(3) }
+        );
+        assert!(matches!(
+            parse_field("top_processes"),
+            Err(UnitParseError::NoUnit)
+        ));
+        assert!(matches!(
+            parse_field("top_processes:MiB"),
+            Err(UnitParseError::Limit(_))
+        ));
     }
 
     #[test]
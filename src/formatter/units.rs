@@ -1,7 +1,8 @@
 use std::fmt::Debug;
-use strum::{Display, EnumString};
+use strum::{Display, EnumString, VariantNames};
 use uom::si::{
-    f32::{Information, Power},
+    energy::{kilowatt_hour, watt_hour},
+    f32::{Energy, Information, Power},
     information::*,
     power::{kilowatt, watt},
     thermodynamic_temperature::{degree_celsius, degree_fahrenheit, kelvin},
@@ -15,7 +16,7 @@ pub trait Unit: Copy {
     fn compute(self, v: Self::Value) -> f32;
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, VariantNames)]
 pub enum MemUnit {
     KiB,
     MiB,
@@ -52,7 +53,7 @@ impl Unit for MemUnit {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, VariantNames)]
 #[strum(ascii_case_insensitive)]
 pub enum TemperatureUnit {
     #[strum(serialize = "c")]
@@ -75,7 +76,7 @@ impl Unit for TemperatureUnit {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, VariantNames)]
 #[strum(ascii_case_insensitive)]
 pub enum PowerUnit {
     #[strum(serialize = "w")]
@@ -94,3 +95,23 @@ impl Unit for PowerUnit {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, VariantNames)]
+#[strum(ascii_case_insensitive)]
+pub enum EnergyUnit {
+    #[strum(serialize = "wh")]
+    WattHour,
+    #[strum(serialize = "kwh")]
+    KiloWattHour,
+}
+
+impl Unit for EnergyUnit {
+    type Value = Energy;
+
+    fn compute(self, v: Self::Value) -> f32 {
+        match self {
+            EnergyUnit::WattHour => v.get::<watt_hour>(),
+            EnergyUnit::KiloWattHour => v.get::<kilowatt_hour>(),
+        }
+    }
+}
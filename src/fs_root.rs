@@ -0,0 +1,159 @@
+use std::{fs, path::PathBuf};
+
+/// Where sysfs and procfs are read from.
+///
+/// This is `/` outside of tests, which point it at a fixture tree instead.
+#[derive(Debug, Clone)]
+pub struct FsRoot {
+    root: PathBuf,
+}
+
+impl Default for FsRoot {
+    fn default() -> Self {
+        Self::new("/")
+    }
+}
+
+impl FsRoot {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// `/sys/class/drm`
+    pub fn drm_class(&self) -> PathBuf {
+        self.root.join("sys/class/drm")
+    }
+
+    /// Returns the names of the DRM cards, e.g. `card0`, sorted, without
+    /// their connectors. Empty if `/sys/class/drm` can't be read.
+    pub fn drm_cards(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(self.drm_class()) else {
+            return Vec::new();
+        };
+
+        let mut cards: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| {
+                name.strip_prefix("card")
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            })
+            .collect();
+        cards.sort_unstable();
+
+        cards
+    }
+
+    /// `/sys/bus/pci/devices/<bus_id>`
+    pub fn pci_device(&self, bus_id: &str) -> PathBuf {
+        self.root.join("sys/bus/pci/devices").join(bus_id)
+    }
+
+    /// Returns the names of the DRM render nodes of the PCI device at
+    /// `bus_id`, e.g. `renderD129`, sorted.
+    pub fn render_nodes(&self, bus_id: &str) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(self.pci_device(bus_id).join("drm")) else {
+            return Vec::new();
+        };
+
+        let mut nodes: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with("renderD"))
+            .collect();
+        nodes.sort_unstable();
+
+        nodes
+    }
+
+    /// `/proc`
+    pub fn proc(&self) -> PathBuf {
+        self.root.join("proc")
+    }
+
+    /// Returns `false` if the PCI device at `bus_id` is runtime suspended.
+    ///
+    /// Reading this doesn't wake the device up.
+    pub fn is_powered_on(&self, bus_id: &str) -> bool {
+        let path = self.pci_device(bus_id).join("power/runtime_status");
+
+        match fs::read_to_string(path) {
+            Ok(status) => status.trim() == "active",
+            // Sometimes the runtime status file doesn't exist or doesn't contain the
+            // expected value
+            Err(_) => true,
+        }
+    }
+}
+
+/// Returns the fixture tree `tests/fixtures/fs/<name>`.
+#[cfg(test)]
+pub fn fixture(name: &str) -> FsRoot {
+    FsRoot::new(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/fs")
+            .join(name),
+    )
+}
+
+/// Writes `/proc/<pid>/stat` under `root` for a process started at
+/// `start_time` clock ticks after boot.
+#[cfg(test)]
+pub fn write_stat(root: &std::path::Path, pid: i32, name: &str, start_time: u64) {
+    let dir = root.join("proc").join(pid.to_string());
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("stat"),
+        format!(
+            "{pid} ({name}) S 1 {pid} {pid} 0 -1 4194560 0 0 0 0 0 0 0 0 20 0 1 0 {start_time} 0 0 \
+             18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n"
+        ),
+    )
+    .unwrap();
+}
+
+/// Returns the empty directory `gpu-usage-waybar-<name>-<pid>` in the
+/// temporary directory, clearing what a previous run left in it.
+#[cfg(test)]
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gpu-usage-waybar-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_powered_on() {
+        assert!(fixture("amd-dgpu").is_powered_on("0000:03:00.0"));
+        assert!(!fixture("amd-suspended").is_powered_on("0000:03:00.0"));
+        // APUs have no runtime power management.
+        assert!(fixture("amd-apu").is_powered_on("0000:c4:00.0"));
+    }
+
+    #[test]
+    fn test_drm_cards() {
+        assert_eq!(fixture("amd-dgpu").drm_cards(), ["card0"]);
+        assert_eq!(
+            fixture("multi-card").drm_cards(),
+            ["card0", "card10", "card2"]
+        );
+        assert!(fixture("nvidia-busy").drm_cards().is_empty());
+    }
+
+    #[test]
+    fn test_render_nodes() {
+        assert_eq!(
+            fixture("nvidia-busy").render_nodes("0000:01:00.0"),
+            ["renderD129"]
+        );
+        assert!(
+            fixture("nvidia-busy")
+                .render_nodes("0000:02:00.0")
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Parser for the amdgpu `gpu_metrics` sysfs file.
+//!
+//! `gpu_metrics` is a binary table filled by the power management firmware,
+//! starting with a header that gives its size and revision. The layouts are
+//! the `gpu_metrics_v*` structs of the kernel's `kgd_pp_interface.h`:
+//!
+//! - `v1.x` on dGPUs, with temperatures in °C and power in W.
+//! - `v2.x` and `v3.x` on APUs, with temperatures in centi-°C and power in mW.
+//!
+//! Fields the firmware doesn't report are all ones.
+
+use uom::si::{
+    f32::Power,
+    power::{milliwatt, watt},
+    thermodynamic_temperature::degree_celsius,
+};
+
+use crate::gpu_status::Temperature;
+
+/// The readings of a `gpu_metrics` table that the AMD backend uses.
+#[derive(Debug, Default, PartialEq)]
+pub struct GpuMetrics {
+    /// Edge temperature on dGPUs, graphics core temperature on APUs.
+    pub temperature: Option<Temperature>,
+    pub temperature_hotspot: Option<Temperature>,
+    pub temperature_mem: Option<Temperature>,
+    /// Graphics engine utilization in percent.
+    pub gfx_activity: Option<u8>,
+    /// Memory controller utilization in percent.
+    pub umc_activity: Option<u8>,
+    /// Video engine (UVD or VCN) utilization in percent.
+    pub vcn_activity: Option<u8>,
+    pub socket_power: Option<Power>,
+}
+
+/// Little-endian reads from a table, `None` for unreported values or reads
+/// past its end.
+struct Table<'a>(&'a [u8]);
+
+impl Table<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.0.get(offset..offset + 2)?;
+        let v = u16::from_le_bytes(bytes.try_into().ok()?);
+
+        (v != u16::MAX).then_some(v)
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.0.get(offset..offset + 4)?;
+        let v = u32::from_le_bytes(bytes.try_into().ok()?);
+
+        (v != u32::MAX).then_some(v)
+    }
+
+    fn percent(&self, offset: usize) -> Option<u8> {
+        self.u16(offset).map(|v| v.min(100) as u8)
+    }
+
+    fn celsius(&self, offset: usize) -> Option<Temperature> {
+        self.u16(offset)
+            .map(|v| Temperature::new::<degree_celsius>(v as f32))
+    }
+
+    fn centi_celsius(&self, offset: usize) -> Option<Temperature> {
+        self.u16(offset)
+            .map(|v| Temperature::new::<degree_celsius>(v as f32 / 100.0))
+    }
+}
+
+/// Parses a `gpu_metrics` table.
+///
+/// Returns `None` for revisions this parser doesn't know, such as `v1.4`
+/// and later of the datacenter GPUs, and for tables shorter than their
+/// header says.
+pub fn parse(blob: &[u8]) -> Option<GpuMetrics> {
+    let table = Table(blob);
+    let size = usize::from(u16::from_le_bytes(blob.get(0..2)?.try_into().ok()?));
+    let (format, content) = (*blob.get(2)?, *blob.get(3)?);
+    if blob.len() < size {
+        return None;
+    }
+
+    // Offsets follow the C layout, where the 64-bit timestamp is aligned to
+    // 8 bytes.
+    let metrics = match (format, content) {
+        // The timestamp comes right after the header.
+        (1, 0) => GpuMetrics {
+            temperature: table.celsius(16),
+            temperature_hotspot: table.celsius(18),
+            temperature_mem: table.celsius(20),
+            gfx_activity: table.percent(28),
+            umc_activity: table.percent(30),
+            vcn_activity: table.percent(32),
+            socket_power: table.u16(34).map(|v| Power::new::<watt>(v as f32)),
+        },
+        (1, 1..=3) => GpuMetrics {
+            temperature: table.celsius(4),
+            temperature_hotspot: table.celsius(6),
+            temperature_mem: table.celsius(8),
+            gfx_activity: table.percent(16),
+            umc_activity: table.percent(18),
+            vcn_activity: table.percent(20),
+            socket_power: table.u16(22).map(|v| Power::new::<watt>(v as f32)),
+        },
+        (2, 0) => GpuMetrics {
+            temperature: table.centi_celsius(16),
+            gfx_activity: table.percent(40),
+            vcn_activity: table.percent(42),
+            socket_power: table.u16(44).map(|v| Power::new::<milliwatt>(v as f32)),
+            ..Default::default()
+        },
+        (2, 1..=4) => GpuMetrics {
+            temperature: table.centi_celsius(4),
+            gfx_activity: table.percent(28),
+            vcn_activity: table.percent(30),
+            socket_power: table.u16(40).map(|v| Power::new::<milliwatt>(v as f32)),
+            ..Default::default()
+        },
+        (3, 0) => GpuMetrics {
+            temperature: table.centi_celsius(4),
+            gfx_activity: table.percent(42),
+            vcn_activity: table.percent(44),
+            socket_power: table.u32(112).map(|v| Power::new::<milliwatt>(v as f32)),
+            ..Default::default()
+        },
+        _ => return None,
+    };
+
+    Some(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/gpu_metrics/",
+                $name
+            ))
+        };
+    }
+
+    fn celsius(v: f32) -> Option<Temperature> {
+        Some(Temperature::new::<degree_celsius>(v))
+    }
+
+    fn watts(v: f32) -> Option<Power> {
+        Some(Power::new::<watt>(v))
+    }
+
+    fn milliwatts(v: f32) -> Option<Power> {
+        Some(Power::new::<milliwatt>(v))
+    }
+
+    #[test]
+    fn test_v1_0() {
+        assert_eq!(
+            parse(fixture!("v1_0.bin")),
+            Some(GpuMetrics {
+                temperature: celsius(60.0),
+                temperature_hotspot: celsius(75.0),
+                temperature_mem: celsius(70.0),
+                gfx_activity: Some(91),
+                umc_activity: Some(40),
+                vcn_activity: Some(12),
+                socket_power: watts(180.0),
+            })
+        );
+    }
+
+    /// Test a table with unreported values, here the VR temperatures.
+    #[test]
+    fn test_v1_3() {
+        assert_eq!(
+            parse(fixture!("v1_3.bin")),
+            Some(GpuMetrics {
+                temperature: celsius(45.0),
+                temperature_hotspot: celsius(52.0),
+                temperature_mem: celsius(56.0),
+                gfx_activity: Some(37),
+                umc_activity: Some(21),
+                vcn_activity: Some(5),
+                socket_power: watts(87.0),
+            })
+        );
+    }
+
+    #[test]
+    fn test_v2_0() {
+        assert_eq!(
+            parse(fixture!("v2_0.bin")),
+            Some(GpuMetrics {
+                temperature: celsius(48.5),
+                gfx_activity: Some(15),
+                vcn_activity: Some(3),
+                socket_power: milliwatts(9500.0),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_v2_1() {
+        assert_eq!(
+            parse(fixture!("v2_1.bin")),
+            Some(GpuMetrics {
+                temperature: celsius(45.0),
+                gfx_activity: Some(12),
+                vcn_activity: Some(8),
+                socket_power: milliwatts(9000.0),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_v3_0() {
+        assert_eq!(
+            parse(fixture!("v3_0.bin")),
+            Some(GpuMetrics {
+                temperature: celsius(51.25),
+                gfx_activity: Some(66),
+                vcn_activity: Some(24),
+                socket_power: milliwatts(23_500.0),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(parse(&[]), None);
+        // Shorter than the header says.
+        assert_eq!(parse(&fixture!("v1_3.bin")[..100]), None);
+
+        let mut v1_4 = fixture!("v1_3.bin").to_vec();
+        v1_4[3] = 4;
+        assert_eq!(parse(&v1_4), None);
+    }
+}
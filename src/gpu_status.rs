@@ -1,45 +1,168 @@
 use amdgpu_sysfs::gpu_handle::PerformanceLevel;
 use color_eyre::eyre::Result;
-use std::fmt::{Display, Write};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Write},
+    sync::Arc,
+};
 use strum::Display;
-use uom::si::{f32::Information, f32::Power};
+use uom::si::{
+    f32::Energy,
+    f32::Information,
+    f32::Power,
+    information::{byte, mebibyte},
+};
 
 use crate::formatter::{self, fields::*, units::*, *};
 
 pub type Temperature = uom::si::f32::ThermodynamicTemperature;
 
-#[derive(Default)]
+/// A single sample of GPU metrics.
+///
+/// (De)serializes to JSON in SI base units (bytes for information), with the
+/// unit spelled out in each key.
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GpuStatusData {
     /// Whether any process is using GPU.
     pub(crate) has_running_processes: bool,
     /// Whether GPU is powered on at the PCI level.
     pub(crate) powered_on: bool,
     /// GPU utilization in percent.
+    #[serde(rename = "gpu_utilization_percent")]
     pub(crate) gpu_utilization: Option<u8>,
     /// Memory used.
+    #[serde(rename = "mem_used_bytes", with = "si::bytes")]
     pub(crate) mem_used: Option<Information>,
     /// Total memory.
+    #[serde(rename = "mem_total_bytes", with = "si::bytes")]
     pub(crate) mem_total: Option<Information>,
+    /// (AMD) GTT, system memory mapped for the GPU, in use.
+    #[serde(rename = "gtt_used_bytes", with = "si::bytes")]
+    pub(crate) gtt_used: Option<Information>,
+    /// (AMD) Total GTT.
+    #[serde(rename = "gtt_total_bytes", with = "si::bytes")]
+    pub(crate) gtt_total: Option<Information>,
+    /// (AMD) VRAM used in the part the CPU can access directly.
+    #[serde(rename = "vis_vram_used_bytes", with = "si::bytes")]
+    pub(crate) vis_vram_used: Option<Information>,
     /// Memory data bus utilization in percent.
+    #[serde(rename = "mem_rw_percent")]
     pub(crate) mem_rw: Option<u8>,
     /// Decoder utilization in percent.
+    #[serde(rename = "decoder_utilization_percent")]
     pub(crate) decoder_utilization: Option<u8>,
     /// Encoder utilization in percent.
+    #[serde(rename = "encoder_utilization_percent")]
     pub(crate) encoder_utilization: Option<u8>,
     /// Temperature.
+    #[serde(rename = "temperature_kelvin", with = "si::kelvin")]
     pub(crate) temperature: Option<Temperature>,
+    /// Hottest spot of the GPU die (AMD junction temperature).
+    #[serde(rename = "temperature_hotspot_kelvin", with = "si::kelvin")]
+    pub(crate) temperature_hotspot: Option<Temperature>,
+    /// Memory temperature.
+    #[serde(rename = "temperature_mem_kelvin", with = "si::kelvin")]
+    pub(crate) temperature_mem: Option<Temperature>,
     /// Power usage.
+    #[serde(rename = "power_watts", with = "si::watts")]
     pub(crate) power: Option<Power>,
     /// (NVIDIA) Performance state.
     pub(crate) p_state: Option<PState>,
     /// (AMD) Performance Level
     pub(crate) p_level: Option<PerformanceLevel>,
     /// Fan speed in percent.
+    #[serde(rename = "fan_speed_percent")]
     pub(crate) fan_speed: Option<u8>,
     /// PCIe TX throughput per second.
+    #[serde(rename = "tx_bytes_per_second", with = "si::bytes")]
     pub(crate) tx: Option<Information>,
     /// PCIe RX throughput per second.
+    #[serde(rename = "rx_bytes_per_second", with = "si::bytes")]
     pub(crate) rx: Option<Information>,
+    /// PCIe link generation, e.g. `4` for 16 GT/s.
+    pub(crate) pcie_gen: Option<u8>,
+    /// PCIe link width in lanes.
+    pub(crate) pcie_width: Option<u8>,
+    /// Highest PCIe link generation supported by the GPU.
+    pub(crate) pcie_max_gen: Option<u8>,
+    /// Widest PCIe link supported by the GPU.
+    pub(crate) pcie_max_width: Option<u8>,
+    /// Energy used since the start or since midnight, see
+    /// [crate::energy::EnergyMeter].
+    #[serde(rename = "energy_joules", with = "si::joules")]
+    pub(crate) energy: Option<Energy>,
+    /// Cost of `energy` at the configured price.
+    pub(crate) cost: Option<f32>,
+    /// Energy counter of the backend in microjoules, from an arbitrary point
+    /// in time.
+    ///
+    /// Kept as an integer because the counters are large, and a float would
+    /// lose the increments between two samples. Only
+    /// [crate::energy::EnergyMeter] needs it, so it isn't recorded.
+    #[serde(skip)]
+    pub(crate) energy_counter: Option<u64>,
+    /// Processes using the GPU, `None` if the backend can't list them.
+    pub(crate) processes: Option<Vec<GpuProcess>>,
+}
+
+/// A process using the GPU.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GpuProcess {
+    pub pid: u32,
+    /// Command name, as in `/proc/<pid>/comm`.
+    pub name: String,
+    /// VRAM used by the process.
+    #[serde(rename = "vram_bytes", with = "si::bytes", default)]
+    pub vram: Option<Information>,
+    /// Share of the GPU used by the process in percent.
+    #[serde(rename = "gpu_utilization_percent", default)]
+    pub gpu_utilization: Option<u8>,
+}
+
+/// Serde adapters storing optional `uom` quantities as plain numbers in a
+/// fixed unit.
+///
+/// Counts such as bytes are written as integers, since an `f32` can't print
+/// e.g. 8 GiB exactly. They are read as floats, which also accepts integers.
+mod si {
+    macro_rules! si_unit {
+        ($name:ident, $quantity:ty, $unit:ty) => {
+            si_unit!($name, $quantity, $unit, |v: f32| v);
+        };
+        ($name:ident, $quantity:ty, $unit:ty, $repr:expr) => {
+            pub mod $name {
+                use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+                pub fn serialize<S: Serializer>(
+                    v: &Option<$quantity>,
+                    s: S,
+                ) -> Result<S::Ok, S::Error> {
+                    v.map(|v| ($repr)(v.get::<$unit>())).serialize(s)
+                }
+
+                pub fn deserialize<'de, D: Deserializer<'de>>(
+                    d: D,
+                ) -> Result<Option<$quantity>, D::Error> {
+                    Ok(Option::<f32>::deserialize(d)?.map(<$quantity>::new::<$unit>))
+                }
+            }
+        };
+    }
+
+    si_unit!(
+        bytes,
+        uom::si::f32::Information,
+        uom::si::information::byte,
+        |v: f32| v.round() as u64
+    );
+    si_unit!(
+        kelvin,
+        crate::gpu_status::Temperature,
+        uom::si::thermodynamic_temperature::kelvin
+    );
+    si_unit!(watts, uom::si::f32::Power, uom::si::power::watt);
+    si_unit!(joules, uom::si::f32::Energy, uom::si::energy::joule);
 }
 
 impl GpuStatusData {
@@ -52,7 +175,9 @@ impl GpuStatusData {
         }
     }
 
-    pub fn get_text<'a>(&self, state: &'a mut State) -> &'a str {
+    /// Formats the text, taking identity fields such as `{gpu_name}` from
+    /// `device`.
+    pub fn get_text<'a>(&self, device: &DeviceInfo, state: &'a mut State) -> &'a str {
         if !self.powered_on {
             return "Off";
         }
@@ -61,11 +186,11 @@ impl GpuStatusData {
             return "Idle";
         }
 
-        state.assemble(self);
+        state.assemble(self, device);
         &state.buffer
     }
 
-    pub fn get_tooltip<'a>(&self, state: &'a mut State) -> &'a str {
+    pub fn get_tooltip<'a>(&self, device: &DeviceInfo, state: &'a mut State) -> &'a str {
         if !self.powered_on {
             return "GPU powered off";
         }
@@ -74,15 +199,20 @@ impl GpuStatusData {
             return "GPU idle";
         }
 
-        state.assemble(self);
+        state.assemble(self, device);
         &state.buffer
     }
 
-    /// Write `field` value to `buffer`.
+    /// Write `field` value to `buffer`, taking identity fields from `device`.
     ///
     /// - Writes "N/A" if `field` is [Field::Unknown].
     /// - Returns [WriteFieldError::FieldIsNone] if `field` is `None`.
-    pub fn write_field(&self, field: Field, buffer: &mut String) -> Result<(), WriteFieldError> {
+    pub fn write_field(
+        &self,
+        device: &DeviceInfo,
+        field: Field,
+        buffer: &mut String,
+    ) -> Result<(), WriteFieldError> {
         let scan_end_index = buffer.len();
 
         macro_rules! u {
@@ -98,14 +228,34 @@ impl GpuStatusData {
         }
 
         match field {
-            Field::Simple(field) => self.write_simple_field(field, buffer)?,
+            Field::Simple(field) => {
+                self.write_simple_field(device, field, buffer)?;
+
+                // Versions and bus IDs may end with zeros after a dot.
+                if field.is_text() {
+                    return Ok(());
+                }
+            }
             Field::Mem {
                 field,
                 unit,
                 precision,
             } => u!(self.get_mem_field(field), unit, precision),
-            Field::Temperature { unit, precision } => u!(self.temperature, unit, precision),
+            Field::Temperature {
+                field,
+                unit,
+                precision,
+            } => u!(self.get_temperature_field(field), unit, precision),
             Field::Power { unit, precision } => u!(self.power, unit, precision),
+            Field::Energy { unit, precision } => u!(self.energy, unit, precision),
+            // Keep the cents, e.g. `0.10`.
+            Field::Cost => {
+                let cost = self.cost.ok_or(WriteFieldError::FieldIsNone)?;
+                write!(buffer, "{cost:.2}").unwrap();
+                return Ok(());
+            }
+            // Process names may end with zeros after a dot, so skip trimming.
+            Field::Processes { limit } => return self.write_processes(limit, buffer),
             Field::Unknown => buffer.push_str("N/A"),
         };
 
@@ -115,9 +265,10 @@ impl GpuStatusData {
     }
 
     /// Returns `true` if the field is [Field::Unknown] or the corresponding value is `None`.
-    pub fn is_field_unavailable(&self, field: Field) -> bool {
+    pub fn is_field_unavailable(&self, device: &DeviceInfo, field: Field) -> bool {
         match field {
             Field::Unknown => true,
+            Field::Simple(field) if field.is_text() => device.text_field(field).is_none(),
             Field::Simple(field) => self.get_simple_field_display(field).is_none(),
             Field::Mem {
                 field,
@@ -125,16 +276,47 @@ impl GpuStatusData {
                 precision: _,
             } => self.get_mem_field(field).is_none(),
             Field::Temperature {
+                field,
                 unit: _,
                 precision: _,
-            } => self.temperature.is_none(),
+            } => self.get_temperature_field(field).is_none(),
             Field::Power {
                 unit: _,
                 precision: _,
             } => self.power.is_none(),
+            Field::Energy {
+                unit: _,
+                precision: _,
+            } => self.energy.is_none(),
+            Field::Cost => self.cost.is_none(),
+            Field::Processes { limit: _ } => self.processes.is_none(),
         }
     }
 
+    /// Returns the numeric value of `field` in its unit.
+    ///
+    /// Returns `None` if the value is unavailable or not numeric, e.g. for
+    /// [SimpleField::PState].
+    pub fn field_value(&self, field: Field) -> Option<f32> {
+        match field {
+            Field::Simple(field) => match self.get_simple_field_display(field)? {
+                SimpleDisplay::U8(v) => Some(v.into()),
+                SimpleDisplay::PState(_) | SimpleDisplay::PLevel(_) => None,
+            },
+            Field::Mem { field, unit, .. } => self.get_mem_field(field).map(|v| unit.compute(v)),
+            Field::Temperature { field, unit, .. } => {
+                self.get_temperature_field(field).map(|v| unit.compute(v))
+            }
+            Field::Power { unit, .. } => self.power.map(|v| unit.compute(v)),
+            Field::Energy { unit, .. } => self.energy.map(|v| unit.compute(v)),
+            Field::Cost => self.cost,
+            Field::Processes { .. } => self.processes.as_ref().map(|p| p.len() as f32),
+            Field::Unknown => None,
+        }
+    }
+
+    /// Returns the reading of `field`, `None` for identity fields which come
+    /// from [DeviceInfo::text_field].
     fn get_simple_field_display(&self, field: SimpleField) -> Option<SimpleDisplay> {
         macro_rules! d {
             ($val:expr) => {
@@ -151,15 +333,31 @@ impl GpuStatusData {
             SimpleField::PState => self.p_state.map(SimpleDisplay::PState),
             SimpleField::PLevel => self.p_level.map(SimpleDisplay::PLevel),
             SimpleField::FanSpeed => d!(self.fan_speed),
+            SimpleField::PcieGen => d!(self.pcie_gen),
+            SimpleField::PcieWidth => d!(self.pcie_width),
+            SimpleField::PcieMaxGen => d!(self.pcie_max_gen),
+            SimpleField::PcieMaxWidth => d!(self.pcie_max_width),
+            SimpleField::GpuName
+            | SimpleField::Vendor
+            | SimpleField::DriverVersion
+            | SimpleField::VbiosVersion
+            | SimpleField::PciBusId
+            | SimpleField::Uuid => None,
         }
     }
 
     fn write_simple_field(
         &self,
+        device: &DeviceInfo,
         field: SimpleField,
         buffer: &mut String,
     ) -> Result<(), WriteFieldError> {
-        if let Some(field_display) = self.get_simple_field_display(field) {
+        if field.is_text() {
+            let text = device
+                .text_field(field)
+                .ok_or(WriteFieldError::FieldIsNone)?;
+            buffer.push_str(text);
+        } else if let Some(field_display) = self.get_simple_field_display(field) {
             write!(buffer, "{field_display}").unwrap();
         } else {
             return Err(WriteFieldError::FieldIsNone);
@@ -168,26 +366,180 @@ impl GpuStatusData {
         Ok(())
     }
 
+    /// Writes one `name (pid): VRAM MiB, GPU%` line per process, using the
+    /// most VRAM first, up to `limit` processes.
+    fn write_processes(
+        &self,
+        limit: Option<usize>,
+        buffer: &mut String,
+    ) -> Result<(), WriteFieldError> {
+        let processes = self
+            .processes
+            .as_ref()
+            .ok_or(WriteFieldError::FieldIsNone)?;
+
+        let mut processes: Vec<&GpuProcess> = processes.iter().collect();
+        processes.sort_by(|a, b| {
+            let vram = |p: &GpuProcess| p.vram.map(|v| v.get::<byte>());
+            vram(b)
+                .partial_cmp(&vram(a))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.pid.cmp(&b.pid))
+        });
+
+        for (i, process) in processes
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .enumerate()
+        {
+            if i > 0 {
+                buffer.push('\n');
+            }
+
+            write!(buffer, "{} ({})", process.name, process.pid).unwrap();
+
+            let mut separator = ": ";
+            if let Some(vram) = process.vram {
+                write!(buffer, "{separator}{:.0} MiB", vram.get::<mebibyte>()).unwrap();
+                separator = ", ";
+            }
+            if let Some(gpu_utilization) = process.gpu_utilization {
+                write!(buffer, "{separator}{gpu_utilization}%").unwrap();
+            }
+        }
+
+        Ok(())
+    }
+
     fn get_mem_field(&self, field: MemField) -> Option<Information> {
         match field {
             MemField::MemUsed => self.mem_used,
             MemField::MemTotal => self.mem_total,
             MemField::Tx => self.tx,
             MemField::Rx => self.rx,
+            MemField::GttUsed => self.gtt_used,
+            MemField::GttTotal => self.gtt_total,
+            MemField::VisVramUsed => self.vis_vram_used,
+        }
+    }
+
+    fn get_temperature_field(&self, field: TemperatureField) -> Option<Temperature> {
+        match field {
+            TemperatureField::Temperature => self.temperature,
+            TemperatureField::TemperatureHotspot => self.temperature_hotspot,
+            TemperatureField::TemperatureMem => self.temperature_mem,
         }
     }
 }
 
+/// Static identity of the device behind a [GpuStatus], queried once when the
+/// backend is initialized.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceInfo {
+    /// Lowercase vendor name, e.g. `nvidia`.
+    pub vendor: String,
+    /// PCI bus ID in sysfs form, e.g. `0000:01:00.0`.
+    pub pci_bus_id: Option<String>,
+    /// Marketing name, e.g. `NVIDIA GeForce RTX 4070`.
+    pub name: Option<String>,
+    pub driver_version: Option<String>,
+    pub vbios_version: Option<String>,
+    pub uuid: Option<String>,
+}
+
+impl DeviceInfo {
+    /// Returns the value of an identity field such as [SimpleField::GpuName],
+    /// `None` for readings and unknown values.
+    ///
+    /// Empty strings count as unknown, e.g. the vendor before a daemon sent
+    /// its identity.
+    pub fn text_field(&self, field: SimpleField) -> Option<&str> {
+        let value = match field {
+            SimpleField::GpuName => self.name.as_deref(),
+            SimpleField::Vendor => Some(self.vendor.as_str()),
+            SimpleField::DriverVersion => self.driver_version.as_deref(),
+            SimpleField::VbiosVersion => self.vbios_version.as_deref(),
+            SimpleField::PciBusId => self.pci_bus_id.as_deref(),
+            SimpleField::Uuid => self.uuid.as_deref(),
+            _ => None,
+        };
+
+        value.filter(|v| !v.is_empty())
+    }
+}
+
+/// Returned by backends when the GPU went away, e.g. after a driver reload,
+/// and has to be initialized again.
+#[derive(Debug)]
+pub struct DeviceLost;
+
+impl Display for DeviceLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("The GPU was lost")
+    }
+}
+
+impl std::error::Error for DeviceLost {}
+
+/// Returned once a backend has no more samples, e.g. a trace played back
+/// without delays.
+#[derive(Debug)]
+pub struct EndOfSamples;
+
+impl Display for EndOfSamples {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("There are no more samples")
+    }
+}
+
+impl std::error::Error for EndOfSamples {}
+
+/// Returned while the PCI device of the GPU is absent, e.g. an unplugged
+/// eGPU.
+#[derive(Debug)]
+pub struct Disconnected {
+    pub pci_bus_id: String,
+}
+
+impl Display for Disconnected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The GPU at {} is disconnected", self.pci_bus_id)
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
 pub trait GpuStatus {
     fn compute(&self) -> Result<GpuStatusData>;
 
+    fn device_info(&self) -> Arc<DeviceInfo>;
+
     /// Compute [GpuStatusData] regardless of idle or power state.
     fn compute_force(&self) -> Result<GpuStatusData> {
         self.compute()
     }
+
+    /// Whether [GpuStatus::compute] blocks until the next sample is due, so
+    /// callers shouldn't wait between samples themselves.
+    fn paces_samples(&self) -> bool {
+        false
+    }
+
+    /// Fields measured in the background, which a sample from
+    /// [GpuStatus::compute_force] may not have a value for yet.
+    fn background_fields(&self) -> Vec<MemField> {
+        Vec::new()
+    }
+
+    /// Whether the samples come from a GPU of this machine, rather than e.g.
+    /// a trace or a daemon, so that its PCI device can be watched.
+    fn is_hardware(&self) -> bool {
+        true
+    }
 }
 
-#[derive(Default, Display, Copy, Clone)]
+#[derive(Default, Display, Copy, Clone, Serialize, Deserialize)]
 pub(crate) enum PState {
     P0,
     P1,
@@ -245,7 +597,9 @@ mod tests {
         let mut buf = String::new();
 
         data.write_field(
+            &DeviceInfo::default(),
             Field::Temperature {
+                field: TemperatureField::Temperature,
                 unit: TemperatureUnit::Celsius,
                 precision: Some(2),
             },
@@ -265,7 +619,9 @@ mod tests {
         let mut buf = String::new();
 
         data.write_field(
+            &DeviceInfo::default(),
             Field::Temperature {
+                field: TemperatureField::Temperature,
                 unit: TemperatureUnit::Celsius,
                 precision: Some(0),
             },
@@ -275,4 +631,133 @@ mod tests {
 
         assert_eq!(buf, "35");
     }
+
+    #[test]
+    fn test_write_identity() {
+        let data = GpuStatusData::default();
+        let device = DeviceInfo {
+            vendor: "nvidia".to_string(),
+            pci_bus_id: Some("0000:01:00.0".to_string()),
+            name: Some("NVIDIA GeForce RTX 4070".to_string()),
+            driver_version: Some("580.95.05".to_string()),
+            ..Default::default()
+        };
+
+        let mut buf = String::new();
+        for field in ["gpu_name", "vendor", "driver_version", "pci_bus_id"] {
+            data.write_field(&device, formatter::parse_field(field).unwrap(), &mut buf)
+                .unwrap();
+            buf.push('|');
+        }
+        // Versions keep their trailing zeros.
+        assert_eq!(
+            buf,
+            "NVIDIA GeForce RTX 4070|nvidia|580.95.05|0000:01:00.0|"
+        );
+
+        let uuid = formatter::parse_field("uuid").unwrap();
+        assert!(data.is_field_unavailable(&device, uuid));
+        assert_eq!(data.field_value(uuid), None);
+
+        // The vendor of a daemon client is unknown until the daemon sends it.
+        assert!(data.is_field_unavailable(
+            &DeviceInfo::default(),
+            formatter::parse_field("vendor").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_write_energy_and_cost() {
+        let data = GpuStatusData {
+            energy: Some(Energy::new::<uom::si::energy::watt_hour>(1234.5)),
+            cost: Some(0.1),
+            ..Default::default()
+        };
+
+        let mut buf = String::new();
+        data.write_field(
+            &DeviceInfo::default(),
+            formatter::parse_field("energy:kwh.2").unwrap(),
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(buf, "1.23");
+
+        let mut buf = String::new();
+        data.write_field(&DeviceInfo::default(), Field::Cost, &mut buf)
+            .unwrap();
+        assert_eq!(buf, "0.10");
+
+        assert!(matches!(
+            formatter::parse_field("energy:j"),
+            Err(UnitParseError::Energy(_))
+        ));
+    }
+
+    #[test]
+    fn test_write_processes() {
+        let process = |pid, name: &str, mib: Option<f32>, gpu| GpuProcess {
+            pid,
+            name: name.to_string(),
+            vram: mib.map(Information::new::<mebibyte>),
+            gpu_utilization: gpu,
+        };
+        let data = GpuStatusData {
+            processes: Some(vec![
+                process(30, "steam", None, None),
+                process(40, "mpv", None, Some(3)),
+                process(10, "Xorg", Some(64.0), Some(12)),
+                process(20, "python3.10", Some(2048.4), None),
+            ]),
+            ..Default::default()
+        };
+
+        let mut buf = String::new();
+        data.write_field(
+            &DeviceInfo::default(),
+            Field::Processes { limit: None },
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(
+            buf,
+            "python3.10 (20): 2048 MiB\nXorg (10): 64 MiB, 12%\nsteam (30)\nmpv (40): 3%"
+        );
+
+        let mut buf = String::new();
+        data.write_field(
+            &DeviceInfo::default(),
+            Field::Processes { limit: Some(1) },
+            &mut buf,
+        )
+        .unwrap();
+        assert_eq!(buf, "python3.10 (20): 2048 MiB");
+
+        assert_eq!(
+            data.field_value(Field::Processes { limit: None }),
+            Some(4.0)
+        );
+        assert!(
+            GpuStatusData::default()
+                .is_field_unavailable(&DeviceInfo::default(), Field::Processes { limit: None })
+        );
+    }
+
+    /// Test that a sample survives a JSON round trip unchanged.
+    #[test]
+    fn test_serde_round_trip() {
+        let data = GpuStatusData {
+            powered_on: true,
+            has_running_processes: true,
+            gpu_utilization: Some(42),
+            temperature: Some(Temperature::new::<degree_celsius>(35.5)),
+            p_state: Some(PState::P2),
+            ..Default::default()
+        };
+
+        let json = sonic_rs::to_string(&data).unwrap();
+        let parsed: GpuStatusData = sonic_rs::from_str(&json).unwrap();
+
+        assert_eq!(sonic_rs::to_string(&parsed).unwrap(), json);
+    }
 }
@@ -0,0 +1,226 @@
+use std::{
+    process::{Command, Stdio},
+    thread,
+};
+
+use strum::Display;
+
+use crate::{
+    alerts::AlertEvent,
+    config::structs::HooksConfig,
+    formatter::fields::Field,
+    gpu_status::{DeviceInfo, GpuStatusData},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+enum Hook {
+    #[strum(serialize = "on_power_on")]
+    PowerOn,
+    #[strum(serialize = "on_power_off")]
+    PowerOff,
+    #[strum(serialize = "on_idle")]
+    Idle,
+    #[strum(serialize = "on_busy")]
+    Busy,
+}
+
+/// Runs the `[hooks]` and alert commands.
+///
+/// The power hooks follow `powered_on` and the idle/busy hooks follow
+/// `has_running_processes`. If `apply_initial_state` is set, the hooks
+/// matching the state of the first sample run, so commands can apply it.
+pub struct Hooks {
+    config: HooksConfig,
+    apply_initial_state: bool,
+    previous: Option<(bool, bool)>,
+}
+
+impl Hooks {
+    pub fn new(config: HooksConfig, apply_initial_state: bool) -> Self {
+        Self {
+            config,
+            apply_initial_state,
+            previous: None,
+        }
+    }
+
+    /// Runs the hooks triggered by `data` and by the alert `events` evaluated
+    /// from it, sampled from `device`.
+    pub fn run(&mut self, data: &GpuStatusData, device: &DeviceInfo, events: &[AlertEvent]) {
+        for hook in self.transitions(data) {
+            let command = match hook {
+                Hook::PowerOn => &self.config.on_power_on,
+                Hook::PowerOff => &self.config.on_power_off,
+                Hook::Idle => &self.config.on_idle,
+                Hook::Busy => &self.config.on_busy,
+            };
+
+            if let Some(command) = command {
+                let mut env = sample_env(data, device);
+                env.push(("GPU_HOOK".to_string(), hook.to_string()));
+                spawn(command, env);
+            }
+        }
+
+        for event in events {
+            if let Some(ref command) = event.hook {
+                let mut env = sample_env(data, device);
+                env.push(("GPU_ALERT_NAME".to_string(), event.name.clone()));
+                env.push(("GPU_ALERT_FIELD".to_string(), event.field.clone()));
+                if let Some(value) = event.value {
+                    env.push(("GPU_ALERT_VALUE".to_string(), value.to_string()));
+                }
+                spawn(command, env);
+            }
+        }
+    }
+
+    fn transitions(&mut self, data: &GpuStatusData) -> Vec<Hook> {
+        let current = (data.powered_on, data.has_running_processes);
+        let previous = self.previous.replace(current);
+
+        if previous.is_none() && !self.apply_initial_state {
+            return Vec::new();
+        }
+
+        let mut hooks = Vec::new();
+
+        if previous.map(|(powered_on, _)| powered_on) != Some(current.0) {
+            hooks.push(if current.0 {
+                Hook::PowerOn
+            } else {
+                Hook::PowerOff
+            });
+        }
+
+        if previous.map(|(_, busy)| busy) != Some(current.1) {
+            hooks.push(if current.1 { Hook::Busy } else { Hook::Idle });
+        }
+
+        hooks
+    }
+}
+
+/// Returns the sample as `GPU_*` environment variables.
+///
+/// Every available field is exported in its default unit, which is appended
+/// to the name, e.g. `GPU_TEMPERATURE_C` or `GPU_MEM_USED_MIB`.
+pub fn sample_env(data: &GpuStatusData, device: &DeviceInfo) -> Vec<(String, String)> {
+    let mut env = vec![
+        (
+            "GPU_POWERED_ON".to_string(),
+            u8::from(data.powered_on).to_string(),
+        ),
+        (
+            "GPU_BUSY".to_string(),
+            u8::from(data.has_running_processes).to_string(),
+        ),
+    ];
+
+    for (name, _, field) in Field::catalog() {
+        let mut value = String::new();
+        if data.write_field(device, field, &mut value).is_err() {
+            continue;
+        }
+
+        let name = name.strip_prefix("gpu_").unwrap_or(&name);
+        let key = match field.unit_name() {
+            Some(unit) => format!("GPU_{name}_{unit}"),
+            None => format!("GPU_{name}"),
+        };
+
+        env.push((key.to_uppercase(), value));
+    }
+
+    env
+}
+
+fn spawn(command: &str, env: Vec<(String, String)>) {
+    // stdout is the status bar protocol, keep hooks out of it.
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn();
+
+    match child {
+        // Reap the hook when it exits so it doesn't linger as a zombie.
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => eprintln!("Warning: failed to run hook `{command}`: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::{
+        f32::Information, information::mebibyte, thermodynamic_temperature::degree_celsius,
+    };
+
+    use super::*;
+    use crate::gpu_status::Temperature;
+
+    fn sample(powered_on: bool, busy: bool) -> GpuStatusData {
+        GpuStatusData {
+            powered_on,
+            has_running_processes: busy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_transitions() {
+        let mut hooks = Hooks::new(HooksConfig::default(), true);
+
+        assert_eq!(
+            hooks.transitions(&sample(false, false)),
+            vec![Hook::PowerOff, Hook::Idle]
+        );
+        assert_eq!(hooks.transitions(&sample(false, false)), vec![]);
+        assert_eq!(hooks.transitions(&sample(true, false)), vec![Hook::PowerOn]);
+        assert_eq!(hooks.transitions(&sample(true, true)), vec![Hook::Busy]);
+        assert_eq!(
+            hooks.transitions(&sample(false, false)),
+            vec![Hook::PowerOff, Hook::Idle]
+        );
+    }
+
+    #[test]
+    fn test_transitions_without_initial_state() {
+        let mut hooks = Hooks::new(HooksConfig::default(), false);
+
+        assert_eq!(hooks.transitions(&sample(true, true)), vec![]);
+        assert_eq!(hooks.transitions(&sample(true, false)), vec![Hook::Idle]);
+    }
+
+    #[test]
+    fn test_sample_env() {
+        let data = GpuStatusData {
+            gpu_utilization: Some(42),
+            mem_used: Some(Information::new::<mebibyte>(512.0)),
+            temperature: Some(Temperature::new::<degree_celsius>(65.5)),
+            ..sample(true, true)
+        };
+
+        assert_eq!(
+            sample_env(
+                &data,
+                &DeviceInfo {
+                    vendor: "amd".to_string(),
+                    ..Default::default()
+                }
+            ),
+            vec![
+                ("GPU_POWERED_ON".to_string(), "1".to_string()),
+                ("GPU_BUSY".to_string(), "1".to_string()),
+                ("GPU_UTILIZATION".to_string(), "42".to_string()),
+                ("GPU_VENDOR".to_string(), "amd".to_string()),
+                ("GPU_MEM_USED_MIB".to_string(), "512".to_string()),
+                ("GPU_TEMPERATURE_C".to_string(), "65.5".to_string()),
+            ]
+        );
+    }
+}
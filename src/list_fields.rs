@@ -0,0 +1,123 @@
+use std::io::{Write, stdout};
+
+use color_eyre::eyre::Result;
+use serde::Serialize;
+
+use crate::{
+    energy::EnergyMeter,
+    formatter::fields::Field,
+    gpu_status::{DeviceInfo, GpuStatus, GpuStatusData},
+};
+
+#[derive(Serialize)]
+struct FieldEntry {
+    name: String,
+    units: &'static [&'static str],
+    value: Option<String>,
+    unit: Option<String>,
+    available: bool,
+}
+
+impl FieldEntry {
+    fn new(
+        name: String,
+        units: &'static [&'static str],
+        field: Field,
+        data: &GpuStatusData,
+        device: &DeviceInfo,
+    ) -> Self {
+        let available = !data.is_field_unavailable(device, field);
+
+        let value = available.then(|| {
+            let mut buffer = String::new();
+            // Availability was checked above, so the field can't be `None`.
+            let _ = data.write_field(device, field, &mut buffer);
+            buffer
+        });
+
+        Self {
+            name,
+            units,
+            value,
+            unit: field.unit_name(),
+            available,
+        }
+    }
+}
+
+/// Prints every format field with its accepted units and a one-shot value.
+///
+/// The value is fetched with [GpuStatus::compute_force], so this may wake up
+/// a suspended GPU.
+pub fn run(
+    gpu_status_handler: &dyn GpuStatus,
+    energy_meter: &mut EnergyMeter,
+    json: bool,
+) -> Result<()> {
+    let mut data = gpu_status_handler.compute_force()?;
+    energy_meter.update_now(&mut data);
+    let device = gpu_status_handler.device_info();
+
+    let entries: Vec<FieldEntry> = Field::catalog()
+        .into_iter()
+        .map(|(name, units, field)| FieldEntry::new(name, units, field, &data, &device))
+        .collect();
+
+    let mut stdout_lock = stdout().lock();
+
+    if json {
+        writeln!(
+            &mut stdout_lock,
+            "{}",
+            sonic_rs::to_string_pretty(&entries)?
+        )?;
+        return Ok(());
+    }
+
+    let rows: Vec<_> = entries
+        .into_iter()
+        .map(|entry| {
+            let units = if entry.units.is_empty() {
+                "-".to_string()
+            } else {
+                entry.units.join(", ")
+            };
+
+            let value = match (entry.value, entry.unit) {
+                // The process list takes a line per process.
+                (Some(_), _) if entry.name == "processes" => {
+                    format!("{} processes", data.processes.as_ref().map_or(0, Vec::len))
+                }
+                (Some(value), Some(unit)) => format!("{value} {unit}"),
+                (Some(value), None) => value,
+                (None, _) => "unavailable".to_string(),
+            };
+
+            (entry.name, units, value)
+        })
+        .collect();
+
+    // Columns are two spaces wider than their longest cell.
+    let name_width = rows.iter().map(|(name, ..)| name.len()).max().unwrap_or(0) + 2;
+    let units_width = rows
+        .iter()
+        .map(|(_, units, _)| units.len())
+        .max()
+        .unwrap_or(0)
+        + 2;
+
+    writeln!(
+        &mut stdout_lock,
+        "{:<name_width$}{:<units_width$}VALUE",
+        "FIELD", "UNITS"
+    )?;
+
+    for (name, units, value) in rows {
+        writeln!(
+            &mut stdout_lock,
+            "{name:<name_width$}{units:<units_width$}{value}"
+        )?;
+    }
+
+    Ok(())
+}
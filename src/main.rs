@@ -1,35 +1,94 @@
+pub mod alerts;
 pub mod amd;
+pub mod classes;
 pub mod config;
+pub mod daemon;
+pub mod dbus;
+pub mod energy;
+pub mod fdinfo;
 pub mod formatter;
+pub mod fs_root;
+pub mod gpu_metrics;
 pub mod gpu_status;
+pub mod hooks;
+pub mod list_fields;
+pub mod mock;
 pub mod nvidia;
+pub mod output;
+pub mod pcie_bw;
+pub mod proc_cache;
+pub mod prometheus;
+pub mod recorder;
+pub mod replay;
+pub mod resilience;
 
 use std::{
-    io::{Write, stdout},
-    sync::OnceLock,
-    time::Duration,
+    ffi::OsString,
+    io::stdout,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::{Result, eyre};
 use nvml_wrapper::Nvml;
-use serde::Serialize;
 
 use crate::{
+    alerts::Alerts,
     amd::{AmdGpuStatus, AmdSysFS},
-    formatter::State,
-    gpu_status::{GpuStatus, GpuStatusData},
+    classes::ClassRules,
+    config::structs::ConfigFile,
+    daemon::Subscription,
+    dbus::{DbusPublisher, Notifier},
+    energy::EnergyMeter,
+    formatter::{
+        Chunk, State,
+        fields::{Field, MemField},
+    },
+    fs_root::FsRoot,
+    gpu_status::{DeviceInfo, Disconnected, EndOfSamples, GpuStatus, GpuStatusData},
+    hooks::Hooks,
+    mock::MockGpuStatus,
     nvidia::NvidiaGpuStatus,
+    output::{Frame, OutputKind},
+    recorder::Recorder,
+    replay::Replay,
+    resilience::Resilient,
 };
 
+/// The GPU backend to use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Backend {
+    /// Detect the GPU brand from the loaded kernel modules
+    #[default]
+    Auto,
+    /// NVIDIA GPUs through NVML
+    Nvidia,
+    /// AMD GPUs through sysfs
+    Amd,
+    /// Synthetic data, for trying out formats without a GPU
+    Mock,
+}
+
 pub enum Instance {
     Nvml(Box<Nvml>),
     Amd(Box<AmdSysFS>),
+    Mock,
 }
 
 impl Instance {
+    pub fn new(backend: Backend) -> Result<Self> {
+        match backend {
+            Backend::Auto => Self::detect(),
+            Backend::Nvidia => Ok(Self::Nvml(Box::new(Nvml::init()?))),
+            Backend::Amd => Ok(Self::Amd(Box::new(AmdSysFS::init()?))),
+            Backend::Mock => Ok(Self::Mock),
+        }
+    }
+
     /// Get the instance based on the GPU brand.
-    pub fn new() -> Result<Self> {
+    fn detect() -> Result<Self> {
         let modules = procfs::modules()?;
 
         if modules.contains_key("nvidia") {
@@ -43,13 +102,152 @@ impl Instance {
     }
 }
 
-pub static INSTANCE: OnceLock<Instance> = OnceLock::new();
+/// Builds the [GpuStatus] selected by `args`.
+fn new_handler(
+    args: &Args,
+    update_interval: Duration,
+    measure_throughput: bool,
+) -> Result<Box<dyn GpuStatus>> {
+    let is_daemon = matches!(args.command, Some(Command::Daemon { .. }));
+
+    if let Some(ref path) = args.replay {
+        return Ok(Box::new(Replay::open(path, args.speed)?));
+    }
+
+    if args.client && !is_daemon {
+        return Ok(Box::new(Subscription::connect(daemon_args(
+            args,
+            update_interval,
+        ))?));
+    }
+
+    // The backend owns the instance, so it is dropped, and NVML shut down,
+    // when the backend is built again.
+    Ok(match Instance::new(args.backend)? {
+        Instance::Nvml(nvml) => Box::new(NvidiaGpuStatus::new(*nvml)?),
+        Instance::Amd(amd_sys_fs) => Box::new(AmdGpuStatus::new(*amd_sys_fs, measure_throughput)?),
+        Instance::Mock => Box::new(MockGpuStatus::new()),
+    })
+}
+
+/// Whether the PCIe throughput is used, as measuring it can be costly.
+///
+/// A single sample, e.g. with `--once`, has no throughput to show. Otherwise
+/// it is used by the raw output, D-Bus and the Prometheus exporter, which
+/// publish every metric, or if it appears in a format, alert or class.
+fn measures_throughput(config: &ConfigFile, args: &Args) -> Result<bool> {
+    match args.command {
+        _ if args.once => return Ok(false),
+        Some(Command::ListFields { .. }) => return Ok(false),
+        Some(Command::Serve { .. }) => return Ok(true),
+        Some(Command::Daemon { .. }) | None => {}
+    }
+
+    if config.general.output == OutputKind::Raw || args.dbus {
+        return Ok(true);
+    }
+
+    let is_throughput = |field: &Field| {
+        matches!(
+            field,
+            Field::Mem {
+                field: MemField::Tx | MemField::Rx,
+                ..
+            }
+        )
+    };
+
+    for format in [&config.text.format, config.tooltip.format()] {
+        let state = State::try_from_format(format)?;
+        let mut fields = state.chunks.iter().filter_map(|chunk| match chunk {
+            Chunk::Variable(field) => Some(field),
+            Chunk::Static(_) => None,
+        });
+
+        if fields.any(is_throughput) {
+            return Ok(true);
+        }
+    }
+
+    let rule_fields = (config.alerts.iter().map(|alert| &alert.field))
+        .chain(config.classes.iter().map(|class| &class.field));
+    for field in rule_fields {
+        // Invalid fields are reported when the rules are built.
+        if formatter::parse_field(field).is_ok_and(|field| is_throughput(&field)) {
+            return Ok(true);
+        }
+    }
 
-fn get_instance() -> &'static Instance {
-    INSTANCE.get_or_init(|| Instance::new().unwrap())
+    Ok(false)
 }
 
-#[derive(Parser, Debug)]
+/// Returns the args for a daemon started by a `--client`, so that it polls
+/// the same GPU with the same config.
+///
+/// `--replay` can't be combined with `--client`, so it isn't passed on.
+fn daemon_args(args: &Args, update_interval: Duration) -> Vec<OsString> {
+    let mut daemon_args: Vec<OsString> = vec![
+        "--interval".into(),
+        update_interval.as_millis().to_string().into(),
+        "--backend".into(),
+    ];
+    if let Some(backend) = args.backend.to_possible_value() {
+        daemon_args.push(backend.get_name().into());
+    }
+    if let Some(ref config) = args.config {
+        daemon_args.extend(["--config".into(), config.clone().into_os_string()]);
+    }
+
+    daemon_args
+}
+
+/// What is done with each sample once per GPU: by the daemon for its
+/// `--client`s, otherwise by the process polling the GPU.
+struct Effects {
+    alerts: Alerts,
+    /// Connected on the first notification, so that alerts with only hooks
+    /// work without a session bus.
+    notifier: Option<Notifier>,
+    hooks: Hooks,
+    recorder: Option<Recorder>,
+}
+
+impl Effects {
+    fn new(config: &ConfigFile, apply_initial_state: bool) -> Result<Self> {
+        Ok(Self {
+            alerts: Alerts::new(&config.alerts)?,
+            notifier: None,
+            hooks: Hooks::new(config.hooks.clone(), apply_initial_state),
+            recorder: Recorder::new(&config.logging)?,
+        })
+    }
+
+    fn run(&mut self, data: &GpuStatusData, device: &DeviceInfo) {
+        let alert_events = self.alerts.evaluate(data, Instant::now());
+
+        for event in alert_events.iter().filter(|event| event.notify) {
+            let result = match self.notifier {
+                Some(ref mut notifier) => Ok(notifier),
+                None => Notifier::new().map(|new| self.notifier.insert(new)),
+            }
+            .and_then(|notifier| notifier.notify(event));
+
+            if let Err(e) = result {
+                eprintln!("Warning: failed to send notification: {e}");
+            }
+        }
+
+        self.hooks.run(data, device, &alert_events);
+
+        if let Some(ref mut recorder) = self.recorder
+            && let Err(e) = recorder.record(data, device, SystemTime::now())
+        {
+            eprintln!("Warning: failed to record sample: {e}");
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     /// Polling interval in milliseconds
@@ -67,61 +265,256 @@ pub struct Args {
     /// MEM USED: {mem_used:MiB}/{mem_total:MiB} MiB ({mem_utilization}%)"
     #[arg(long)]
     tooltip_format: Option<String>,
+
+    /// Print a single sample and exit instead of polling forever
+    #[arg(long)]
+    once: bool,
+
+    /// Print only the formatted text, same as `--output plain`
+    #[arg(long, conflicts_with = "output")]
+    plain: bool,
+
+    /// The status bar protocol to write
+    #[arg(long)]
+    output: Option<OutputKind>,
+
+    /// Render samples from a shared daemon instead of polling the GPU,
+    /// starting the daemon if it isn't running
+    #[arg(long)]
+    client: bool,
+
+    /// Publish samples on the session bus as `io.github.PolpOnline.GpuUsageWaybar`
+    #[arg(long)]
+    dbus: bool,
+
+    /// Path of the config file, `$XDG_CONFIG_HOME/gpu_usage_waybar.toml` by
+    /// default
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// The GPU backend to use
+    #[arg(long, value_enum, default_value_t)]
+    backend: Backend,
+
+    /// Play back a trace recorded by `[logging]` instead of polling the GPU
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["client", "backend"])]
+    replay: Option<PathBuf>,
+
+    /// Playback speed of `--replay`, 0 plays the trace once without delays
+    #[arg(long, default_value_t = 1.0, requires = "replay")]
+    speed: f64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// List every format field with its units, current value and availability
+    ListFields {
+        /// Print the list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Serve metrics for Prometheus over HTTP on `/metrics`
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:9840")]
+        listen: SocketAddr,
+    },
+    /// Poll the GPU once for all `--client` instances and publish samples
+    /// over a Unix socket
+    Daemon {
+        /// Exit after a while without subscribers
+        #[arg(long)]
+        exit_when_unused: bool,
+    },
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
 
-    let mut config = config::get_or_init_config()?;
-
     let args = Args::parse();
 
+    let mut config = config::get_or_init_config(&config::config_path(args.config.as_deref())?)?;
+
     config.merge_args_into_config(&args)?;
 
-    let gpu_status_handler: Box<dyn GpuStatus> = match get_instance() {
-        Instance::Nvml(nvml) => Box::new(NvidiaGpuStatus::new(nvml)?),
-        Instance::Amd(amd_sys_fs) => Box::new(AmdGpuStatus::new(amd_sys_fs)?),
-    };
+    let update_interval = Duration::from_millis(config.general.interval);
+    let measure_throughput = measures_throughput(&config, &args)?;
+
+    let gpu_status_handler = Resilient::new(
+        {
+            let args = args.clone();
+            Box::new(move || new_handler(&args, update_interval, measure_throughput))
+        },
+        FsRoot::default(),
+        update_interval,
+        config.errors.max_backoff,
+    );
+
+    match args.command {
+        Some(Command::ListFields { json }) => {
+            return list_fields::run(
+                &gpu_status_handler,
+                &mut EnergyMeter::new(&config.energy),
+                json,
+            );
+        }
+        Some(Command::Serve { listen }) => {
+            return prometheus::serve(
+                &gpu_status_handler,
+                &mut EnergyMeter::new(&config.energy),
+                listen,
+            );
+        }
+        Some(Command::Daemon { exit_when_unused }) => {
+            let mut energy_meter = EnergyMeter::new(&config.energy);
+            let mut effects = Effects::new(&config, true)?;
+
+            return daemon::run(
+                &gpu_status_handler,
+                update_interval,
+                exit_when_unused,
+                &mut |data, device| {
+                    energy_meter.update_now(data);
+                    effects.run(data, device);
+                },
+            );
+        }
+        None => {}
+    }
+
+    // With `--once`, the only sample is taken up front, as forcing one would
+    // wake a power-gated GPU on every run.
+    let mut first_sample = args.once.then(|| gpu_status_handler.compute());
 
     // If the the user didn't set a custom tooltip format,
     // automatically hide any unavailable fields.
     if !config.tooltip.is_format_set() {
         // Fetch the data once to determine which fields are available
-        let gpu_status_data = gpu_status_handler.compute_force()?;
-        config.tooltip.retain_lines_with_values(&gpu_status_data);
+        let forced_sample = match first_sample {
+            Some(_) => None,
+            None => gpu_status_handler
+                .compute_force()
+                .inspect_err(|e| {
+                    eprintln!("Warning: unable to tell which fields are available: {e:#}")
+                })
+                .ok(),
+        };
+        // The error frame doesn't show the tooltip format.
+        let gpu_status_data = match first_sample {
+            Some(ref result) => result.as_ref().ok(),
+            None => forced_sample.as_ref(),
+        };
+
+        if let Some(gpu_status_data) = gpu_status_data {
+            config.tooltip.retain_lines_with_values(
+                gpu_status_data,
+                &gpu_status_handler.device_info(),
+                &gpu_status_handler.background_fields(),
+            );
+        }
     }
 
     let mut text_state = State::try_from_format(&config.text.format)?;
     let mut tooltip_state = State::try_from_format(config.tooltip.format())?;
 
-    let update_interval = Duration::from_millis(config.general.interval);
+    let dbus_publisher = args.dbus.then(DbusPublisher::new).transpose()?;
+
+    let class_rules = ClassRules::new(&config.classes)?;
+    // The daemon takes care of them for its clients, which would otherwise
+    // each do it once.
+    //
+    // Waybar runs `--once` on every interval, which would apply the initial
+    // state of the hooks each time.
+    let mut effects = (!args.client)
+        .then(|| Effects::new(&config, !args.once))
+        .transpose()?;
+    // The daemon adds up the energy for its clients, and `--once` has a
+    // single sample to add up.
+    let mut energy_meter = (!args.client && !args.once).then(|| EnergyMeter::new(&config.energy));
+
+    let mut emitter = config.general.output.emitter(&config.general.colors);
 
     let mut stdout_lock = stdout().lock();
 
+    emitter.start(&mut stdout_lock)?;
+
     loop {
-        let gpu_status_data = gpu_status_handler.compute()?;
+        let sample = first_sample
+            .take()
+            .unwrap_or_else(|| gpu_status_handler.compute());
 
-        let output = format_output(&gpu_status_data, &mut text_state, &mut tooltip_state);
+        let mut gpu_status_data = match sample {
+            Ok(data) => data,
+            Err(e) if e.downcast_ref::<EndOfSamples>().is_some() => return Ok(()),
+            Err(e) => {
+                let (text, class) = if e.downcast_ref::<Disconnected>().is_some() {
+                    (
+                        &config.errors.disconnected_text,
+                        &config.errors.disconnected_class,
+                    )
+                } else {
+                    (&config.errors.text, &config.errors.class)
+                };
 
-        writeln!(&mut stdout_lock, "{}", sonic_rs::to_string(&output)?)?;
+                let error = format!("{e:#}");
+                let frame = Frame {
+                    data: &GpuStatusData::default(),
+                    text,
+                    tooltip: &error,
+                    class: Some(class),
+                    error: Some(&error),
+                };
 
-        std::thread::sleep(update_interval);
-    }
-}
+                emitter.emit(&mut stdout_lock, &frame)?;
 
-fn format_output<'t, 'u>(
-    gpu_status: &GpuStatusData,
-    text_state: &'t mut State,
-    tooltip_state: &'u mut State,
-) -> OutputFormat<'t, 'u> {
-    OutputFormat {
-        text: gpu_status.get_text(text_state),
-        tooltip: gpu_status.get_tooltip(tooltip_state),
-    }
-}
+                // The error is shown in the frame, a failed exit would only
+                // make the bar drop it.
+                if args.once {
+                    return Ok(());
+                }
+
+                std::thread::sleep(update_interval);
+                continue;
+            }
+        };
+
+        if let Some(ref mut energy_meter) = energy_meter {
+            energy_meter.update_now(&mut gpu_status_data);
+        }
 
-#[derive(Default, Serialize)]
-struct OutputFormat<'t, 'u> {
-    text: &'t str,
-    tooltip: &'u str,
+        // Shared with the backend, and only replaced when it is rebuilt.
+        let device_info = gpu_status_handler.device_info();
+
+        let frame = Frame {
+            data: &gpu_status_data,
+            text: gpu_status_data.get_text(&device_info, &mut text_state),
+            tooltip: gpu_status_data.get_tooltip(&device_info, &mut tooltip_state),
+            class: class_rules.class(&gpu_status_data),
+            error: None,
+        };
+
+        emitter.emit(&mut stdout_lock, &frame)?;
+
+        if let Some(ref dbus_publisher) = dbus_publisher
+            && let Err(e) = dbus_publisher.publish(&gpu_status_data)
+        {
+            eprintln!("Warning: failed to publish on D-Bus: {e}");
+        }
+
+        if let Some(ref mut effects) = effects {
+            effects.run(&gpu_status_data, &device_info);
+        }
+
+        if args.once {
+            return Ok(());
+        }
+
+        // The daemon or a replayed trace paces the samples in that case.
+        if !gpu_status_handler.paces_samples() {
+            std::thread::sleep(update_interval);
+        }
+    }
 }
@@ -0,0 +1,206 @@
+use std::{
+    f32::consts::TAU,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre::Result;
+use uom::si::{
+    f32::Information, f32::Power, information::mebibyte, power::watt,
+    thermodynamic_temperature::degree_celsius,
+};
+
+use crate::gpu_status::{DeviceInfo, GpuProcess, GpuStatus, GpuStatusData, PState, Temperature};
+
+/// Length of a full Off, Idle, Busy cycle.
+const CYCLE: Duration = Duration::from_secs(60);
+/// The GPU is off for the start of each cycle...
+const OFF_UNTIL: Duration = Duration::from_secs(10);
+/// ...then idle, then busy until the end of the cycle.
+const IDLE_UNTIL: Duration = Duration::from_secs(20);
+/// Period of the load wave while busy.
+const LOAD_PERIOD: Duration = Duration::from_secs(16);
+
+const MEM_TOTAL_MIB: f32 = 8192.0;
+
+/// A [GpuStatus] generating synthetic data, for trying out formats and bars
+/// without a GPU.
+///
+/// Each [CYCLE] the GPU is off, then idle, then busy. While busy, the load
+/// follows a sine wave, memory usage ramps up, and temperature, power and fan
+/// speed follow the load.
+///
+/// Cycles start on the wall clock's minutes rather than when the mock is
+/// created, so that `--once` shows every state depending on the time it runs.
+pub struct MockGpuStatus {
+    device_info: Arc<DeviceInfo>,
+}
+
+impl MockGpuStatus {
+    pub fn new() -> Self {
+        Self {
+            device_info: Arc::new(DeviceInfo {
+                vendor: "mock".to_string(),
+                name: Some("Mock GPU".to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+impl Default for MockGpuStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GpuStatus for MockGpuStatus {
+    fn compute(&self) -> Result<GpuStatusData> {
+        let since_epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Ok(sample_at(since_epoch))
+    }
+
+    fn compute_force(&self) -> Result<GpuStatusData> {
+        Ok(busy_sample(Duration::ZERO))
+    }
+
+    fn device_info(&self) -> Arc<DeviceInfo> {
+        Arc::clone(&self.device_info)
+    }
+
+    fn is_hardware(&self) -> bool {
+        false
+    }
+}
+
+/// Returns the sample `elapsed` after the start of a cycle.
+fn sample_at(elapsed: Duration) -> GpuStatusData {
+    let in_cycle = Duration::from_nanos((elapsed.as_nanos() % CYCLE.as_nanos()) as u64);
+
+    if in_cycle < OFF_UNTIL {
+        GpuStatusData::default()
+    } else if in_cycle < IDLE_UNTIL {
+        GpuStatusData {
+            powered_on: true,
+            processes: Some(Vec::new()),
+            ..Default::default()
+        }
+    } else {
+        busy_sample(in_cycle - IDLE_UNTIL)
+    }
+}
+
+/// Returns the sample `busy_for` into the busy phase.
+fn busy_sample(busy_for: Duration) -> GpuStatusData {
+    let busy_phase = (CYCLE - IDLE_UNTIL).as_secs_f32();
+    let t = busy_for.as_secs_f32();
+
+    let load = 0.5 + 0.45 * (TAU * t / LOAD_PERIOD.as_secs_f32()).sin();
+    let ramp = t / busy_phase;
+
+    let mem_used_mib = 512.0 + ramp * (MEM_TOTAL_MIB * 0.75 - 512.0);
+
+    let percent = |v: f32| (v * 100.0).round() as u8;
+
+    GpuStatusData {
+        powered_on: true,
+        has_running_processes: true,
+        gpu_utilization: Some(percent(load)),
+        mem_used: Some(Information::new::<mebibyte>(mem_used_mib)),
+        mem_total: Some(Information::new::<mebibyte>(MEM_TOTAL_MIB)),
+        mem_rw: Some(percent(load * 0.6)),
+        decoder_utilization: Some(percent(load * 0.2)),
+        encoder_utilization: Some(percent(load * 0.1)),
+        temperature: Some(Temperature::new::<degree_celsius>(40.0 + 40.0 * load)),
+        temperature_hotspot: Some(Temperature::new::<degree_celsius>(45.0 + 50.0 * load)),
+        temperature_mem: Some(Temperature::new::<degree_celsius>(50.0 + 30.0 * load)),
+        power: Some(Power::new::<watt>(30.0 + 170.0 * load)),
+        p_state: Some(if load > 0.5 { PState::P0 } else { PState::P2 }),
+        fan_speed: Some(percent(0.3 + 0.6 * load)),
+        pcie_gen: Some(4),
+        pcie_width: Some(16),
+        pcie_max_gen: Some(4),
+        pcie_max_width: Some(16),
+        tx: Some(Information::new::<mebibyte>(200.0 * load)),
+        rx: Some(Information::new::<mebibyte>(50.0 * load)),
+        processes: Some(vec![
+            GpuProcess {
+                pid: 1200,
+                name: "Xorg".to_string(),
+                vram: Some(Information::new::<mebibyte>(256.0)),
+                gpu_utilization: Some(percent(load * 0.1)),
+            },
+            GpuProcess {
+                pid: 4242,
+                name: "python3".to_string(),
+                vram: Some(Information::new::<mebibyte>(mem_used_mib - 256.0)),
+                gpu_utilization: Some(percent(load * 0.9)),
+            },
+        ]),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatter::State;
+
+    fn render(elapsed: Duration) -> (String, String) {
+        let mut text_state = State::try_from_format("{gpu_utilization}%").unwrap();
+        let mut tooltip_state =
+            State::try_from_format("{mem_used:MiB.0}/{mem_total:MiB} MiB").unwrap();
+
+        let data = sample_at(elapsed);
+
+        (
+            data.get_text(&DeviceInfo::default(), &mut text_state)
+                .to_string(),
+            data.get_tooltip(&DeviceInfo::default(), &mut tooltip_state)
+                .to_string(),
+        )
+    }
+
+    /// Test that a cycle goes through every state of `get_text` and
+    /// `get_tooltip`.
+    #[test]
+    fn test_cycle() {
+        let secs = Duration::from_secs;
+
+        assert_eq!(
+            render(secs(0)),
+            ("Off".to_string(), "GPU powered off".to_string())
+        );
+        assert_eq!(
+            render(secs(15)),
+            ("Idle".to_string(), "GPU idle".to_string())
+        );
+        assert_eq!(
+            render(secs(20)),
+            ("50%".to_string(), "512/8192 MiB".to_string())
+        );
+        // A quarter of the load period into the busy phase, the load peaks.
+        assert_eq!(render(secs(24)).0, "95%");
+        // The cycle starts over.
+        assert_eq!(render(CYCLE + secs(5)).0, "Off");
+    }
+
+    #[test]
+    fn test_memory_ramps_up() {
+        let mem_used = |secs| sample_at(Duration::from_secs(secs)).mem_used.unwrap();
+
+        assert!(mem_used(30) < mem_used(40));
+        assert!(mem_used(40) < mem_used(59));
+    }
+
+    #[test]
+    fn test_compute_force_has_every_field() {
+        let data = MockGpuStatus::new().compute_force().unwrap();
+
+        assert!(data.powered_on && data.has_running_processes);
+        assert!(data.temperature.is_some() && data.tx.is_some());
+    }
+}
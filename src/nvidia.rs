@@ -1,11 +1,18 @@
-use std::fs;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Report, Result, WrapErr};
 use nvml_wrapper::{
     Device, Nvml,
     enum_wrappers::device::{PcieUtilCounter, PerformanceState, TemperatureSensor},
+    enums::device::UsedGpuMemory,
+    error::NvmlError,
 };
-use procfs::process::{FDTarget, all_processes};
+use procfs::process::FDTarget;
 use uom::si::{
     f32::Information,
     f32::Power,
@@ -14,52 +21,93 @@ use uom::si::{
     thermodynamic_temperature::degree_celsius,
 };
 
-use crate::gpu_status::{GpuStatus, GpuStatusData, PState, Temperature};
+use crate::{
+    fs_root::FsRoot,
+    gpu_status::{
+        DeviceInfo, DeviceLost, GpuProcess, GpuStatus, GpuStatusData, PState, Temperature,
+    },
+    proc_cache::ProcessCache,
+};
 
-pub struct NvidiaGpuStatus<'a> {
-    device: Device<'a>,
+pub struct NvidiaGpuStatus {
+    nvml: Nvml,
+    /// PCI bus ID as NVML reports it, to look up the device.
+    nvml_bus_id: String,
     bus_id: String,
+    device_info: Arc<DeviceInfo>,
+    root: FsRoot,
+    /// The device files that mean a process uses the GPU, see [device_nodes].
+    nodes: Vec<PathBuf>,
+    process_cache: RefCell<ProcessCache>,
 }
 
-impl NvidiaGpuStatus<'_> {
-    pub fn new(instance: &'static Nvml) -> Result<Self> {
+impl NvidiaGpuStatus {
+    pub fn new(instance: Nvml) -> Result<Self> {
         let device = instance.device_by_index(0)?;
 
         // Query PCI info just once
         // NVML returns a PCI domain up to 0xffffffff; need to truncate
         // to match sysfs
-        let bus_id = device.pci_info()?.bus_id.chars().skip(4).collect();
+        let nvml_bus_id = device.pci_info()?.bus_id;
+        let bus_id: String = nvml_bus_id.chars().skip(4).collect();
+
+        let device_info = DeviceInfo {
+            vendor: "nvidia".to_string(),
+            pci_bus_id: Some(bus_id.to_lowercase()),
+            name: device.name().ok(),
+            driver_version: instance.sys_driver_version().ok(),
+            vbios_version: device.vbios_version().ok(),
+            uuid: device.uuid().ok(),
+        };
+
+        let root = FsRoot::default();
+        let nodes = device_nodes(&root, device.minor_number()?, &bus_id.to_lowercase());
 
-        Ok(Self { device, bus_id })
+        Ok(Self {
+            nvml: instance,
+            nvml_bus_id,
+            bus_id,
+            device_info: Arc::new(device_info),
+            root,
+            nodes,
+            process_cache: RefCell::default(),
+        })
     }
 }
 
 enum GpuPowerState {
     Off,
     OnNoProcess,
-    PoweredOnInUse,
+    PoweredOnInUse(Vec<GpuProcess>),
 }
 
-fn is_powered_on(bus_id: &str) -> Result<bool> {
-    let path = format!("/sys/bus/pci/devices/{bus_id}/power/runtime_status");
-    let status = match fs::read_to_string(path) {
-        Ok(s) => s,
-        Err(_) => {
-            // Sometimes the runtime status file doesn't exist or doesn't contain the
-            // expected value
-            return Ok(true);
-        }
-    };
-    let status = status.trim().to_string();
-    let powered_on = status == "active";
-    Ok(powered_on)
+/// Returns the device files that a process using the GPU with minor number
+/// `minor` and PCI bus ID `bus_id` has open.
+///
+/// Besides `/dev/nvidiaN`, CUDA programs may only hold the shared
+/// `/dev/nvidiactl` and `/dev/nvidia-uvm`, and Vulkan or EGL programs the DRM
+/// render node of the card. The KMS node, `/dev/dri/cardN`, doesn't count:
+/// the compositor keeps it open on hybrid laptops even while the GPU idles.
+fn device_nodes(root: &FsRoot, minor: u32, bus_id: &str) -> Vec<PathBuf> {
+    let mut nodes = vec![
+        PathBuf::from(format!("/dev/nvidia{minor}")),
+        PathBuf::from("/dev/nvidiactl"),
+        PathBuf::from("/dev/nvidia-uvm"),
+    ];
+    nodes.extend(
+        root.render_nodes(bus_id)
+            .into_iter()
+            .map(|node| Path::new("/dev/dri").join(node)),
+    );
+
+    nodes
 }
 
-/// Returns `true` if there is any process currently using GPU 0.
+/// Returns the processes currently using the GPU, without their VRAM.
 ///
-/// This function checks whether `/dev/nvidia0` is opened by any process
-/// other than the current one without waking up the GPU by scanning
-/// `/proc/*/fd`.
+/// This function lists the processes other than the current one that have
+/// one of `nodes` open without waking up the GPU by scanning `/proc/*/fd`.
+/// `cache` keeps the scan from opening the fds of every process each time.
 ///
 /// # Note
 ///
@@ -71,49 +119,106 @@ fn is_powered_on(bus_id: &str) -> Result<bool> {
 /// # References
 ///
 /// <https://wiki.archlinux.org/title/PRIME#NVIDIA>
-fn has_running_processes() -> bool {
-    let procs = all_processes().expect("Can't read /proc");
+fn running_processes(
+    cache: &mut ProcessCache,
+    root: &FsRoot,
+    nodes: &[PathBuf],
+) -> Result<Vec<GpuProcess>> {
+    let users = cache
+        .scan(root, |proc| {
+            proc.fd().is_ok_and(|fds| {
+                fds.flatten()
+                    .any(|fd| matches!(fd.target, FDTarget::Path(ref path) if nodes.contains(path)))
+            })
+        })
+        .wrap_err("Unable to read /proc")?;
 
-    for proc in procs.flatten() {
-        if proc.pid == std::process::id() as i32 {
-            continue;
-        }
-
-        let Ok(fds) = proc.fd() else {
-            continue;
-        };
+    Ok(users
+        .into_iter()
+        .map(|(pid, name)| GpuProcess {
+            pid: pid as u32,
+            name,
+            vram: None,
+            gpu_utilization: None,
+        })
+        .collect())
+}
 
-        for fd in fds.flatten() {
-            if let FDTarget::Path(ref path) = fd.target
-                && path == "/dev/nvidia0"
-            {
-                return true;
-            }
-        }
+impl NvidiaGpuStatus {
+    /// Looks up the device, which borrows `nvml`.
+    ///
+    /// Only call this once the GPU is in use, as NVML may wake it up.
+    fn device(&self) -> Result<Device<'_>> {
+        self.nvml
+            .device_by_pci_bus_id(self.nvml_bus_id.as_str())
+            .map_err(|e| {
+                if is_lost(&e) {
+                    Report::new(e).wrap_err(DeviceLost)
+                } else {
+                    Report::new(e)
+                }
+            })
     }
 
-    false
-}
+    fn running_processes(&self) -> Result<Vec<GpuProcess>> {
+        running_processes(
+            &mut self.process_cache.borrow_mut(),
+            &self.root,
+            &self.nodes,
+        )
+    }
 
-impl NvidiaGpuStatus<'_> {
     fn detect_gpu_presence(&self) -> Result<GpuPowerState> {
-        if !is_powered_on(&self.bus_id)? {
+        if !self.root.is_powered_on(&self.bus_id) {
             return Ok(GpuPowerState::Off);
         }
 
-        if !has_running_processes() {
+        let processes = self.running_processes()?;
+
+        if processes.is_empty() {
             return Ok(GpuPowerState::OnNoProcess);
         }
 
-        Ok(GpuPowerState::PoweredOnInUse)
+        Ok(GpuPowerState::PoweredOnInUse(processes))
+    }
+
+    /// Fills in the VRAM of `processes`.
+    ///
+    /// NVML wakes up the GPU, so this is only called once the GPU is in use.
+    fn add_process_vram(device: &Device, processes: &mut [GpuProcess]) {
+        let mut vram = HashMap::new();
+
+        // A process may show up in both lists with the same memory.
+        let infos = [
+            device.running_compute_processes(),
+            device.running_graphics_processes(),
+        ];
+        for info in infos.into_iter().flatten().flatten() {
+            if let UsedGpuMemory::Used(bytes) = info.used_gpu_memory {
+                let used = vram.entry(info.pid).or_insert(0);
+                *used = bytes.max(*used);
+            }
+        }
+
+        for process in processes {
+            process.vram = vram
+                .get(&process.pid)
+                .map(|&bytes| Information::new::<byte>(bytes as f32));
+        }
     }
 
-    fn collect_active_gpu_stats(&self) -> GpuStatusData {
-        let device = &self.device;
-        let utilization_rates = device.utilization_rates().ok();
+    fn collect_active_gpu_stats(&self, mut processes: Vec<GpuProcess>) -> Result<GpuStatusData> {
+        let device = &self.device()?;
+        let utilization_rates = match device.utilization_rates() {
+            Ok(u) => Some(u),
+            Err(e) if is_lost(&e) => return Err(Report::new(e).wrap_err(DeviceLost)),
+            Err(_) => None,
+        };
         let memory_info_in_bytes = device.memory_info().ok();
 
-        GpuStatusData {
+        Self::add_process_vram(device, &mut processes);
+
+        Ok(GpuStatusData {
             powered_on: true,
             has_running_processes: true,
             gpu_utilization: utilization_rates.as_ref().map(|u| u.gpu as u8),
@@ -140,8 +245,17 @@ impl NvidiaGpuStatus<'_> {
                 .power_usage()
                 .ok()
                 .map(|p| Power::new::<milliwatt>(p as f32)),
+            energy_counter: device
+                .total_energy_consumption()
+                .ok()
+                // Millijoules to microjoules.
+                .map(|e| e.saturating_mul(1000)),
             p_state: device.performance_state().ok().map(|p| p.into()),
             fan_speed: device.fan_speed(0u32).ok().map(|f| f as u8),
+            pcie_gen: device.current_pcie_link_gen().ok().map(|g| g as u8),
+            pcie_width: device.current_pcie_link_width().ok().map(|w| w as u8),
+            pcie_max_gen: device.max_pcie_link_gen().ok().map(|g| g as u8),
+            pcie_max_width: device.max_pcie_link_width().ok().map(|w| w as u8),
             tx: device
                 .pcie_throughput(PcieUtilCounter::Send)
                 .ok()
@@ -150,12 +264,25 @@ impl NvidiaGpuStatus<'_> {
                 .pcie_throughput(PcieUtilCounter::Receive)
                 .ok()
                 .map(|t| Information::new::<kilobyte>(t as f32)),
+            processes: Some(processes),
             ..Default::default()
-        }
+        })
     }
 }
 
-impl GpuStatus for NvidiaGpuStatus<'_> {
+/// Returns `true` for errors after which NVML has to be initialized again.
+fn is_lost(error: &NvmlError) -> bool {
+    matches!(
+        error,
+        NvmlError::GpuLost
+            | NvmlError::ResetRequired
+            | NvmlError::Uninitialized
+            | NvmlError::DriverNotLoaded
+            | NvmlError::LibRmVersionMismatch
+    )
+}
+
+impl GpuStatus for NvidiaGpuStatus {
     fn compute(&self) -> Result<GpuStatusData> {
         // GPU status computation is split into two stages to avoid inadvertently
         // waking up the NVIDIA GPU during idle periods:
@@ -184,16 +311,21 @@ impl GpuStatus for NvidiaGpuStatus<'_> {
             GpuPowerState::OnNoProcess => GpuStatusData {
                 powered_on: true,
                 has_running_processes: false,
+                processes: Some(Vec::new()),
                 ..Default::default()
             },
-            GpuPowerState::PoweredOnInUse => self.collect_active_gpu_stats(),
+            GpuPowerState::PoweredOnInUse(processes) => self.collect_active_gpu_stats(processes)?,
         };
 
         Ok(gpu_status)
     }
 
     fn compute_force(&self) -> Result<GpuStatusData> {
-        Ok(self.collect_active_gpu_stats())
+        self.collect_active_gpu_stats(self.running_processes()?)
+    }
+
+    fn device_info(&self) -> Arc<DeviceInfo> {
+        Arc::clone(&self.device_info)
     }
 }
 
@@ -220,3 +352,60 @@ impl From<PerformanceState> for PState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_root;
+
+    #[test]
+    fn test_device_nodes() {
+        assert_eq!(
+            device_nodes(&fs_root::fixture("nvidia-busy"), 0, "0000:01:00.0"),
+            [
+                "/dev/nvidia0",
+                "/dev/nvidiactl",
+                "/dev/nvidia-uvm",
+                "/dev/dri/renderD129",
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_running_processes() {
+        let running_processes = |fixture| {
+            let root = fs_root::fixture(fixture);
+            let nodes = device_nodes(&root, 0, "0000:01:00.0");
+
+            running_processes(&mut ProcessCache::default(), &root, &nodes).unwrap()
+        };
+
+        // 4244 uses another GPU.
+        let processes: Vec<_> = running_processes("nvidia-busy")
+            .into_iter()
+            .map(|p| (p.pid, p.name))
+            .collect();
+        assert_eq!(
+            processes,
+            [
+                (4242, "Xorg".to_string()),
+                (4243, "firefox".to_string()),
+                (4245, "nvidia-smi".to_string()),
+            ]
+        );
+        // Neither the render node of the integrated GPU nor the KMS node of
+        // the NVIDIA GPU keeps it busy.
+        assert!(running_processes("nvidia-idle").is_empty());
+
+        // An unreadable /proc is an error, not a panic.
+        assert!(
+            super::running_processes(
+                &mut ProcessCache::default(),
+                &FsRoot::new("/nonexistent"),
+                &[]
+            )
+            .is_err()
+        );
+    }
+}
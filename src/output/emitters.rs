@@ -0,0 +1,209 @@
+use std::{collections::HashMap, io::Write};
+
+use color_eyre::eyre::Result;
+use serde::Serialize;
+
+use crate::output::{Emitter, Frame};
+
+/// Returns the color of the class of `frame` in `colors`, for bars without
+/// CSS.
+fn class_color<'a>(colors: &'a HashMap<String, String>, frame: &Frame) -> Option<&'a str> {
+    frame
+        .class
+        .and_then(|class| colors.get(class))
+        .map(String::as_str)
+}
+
+/// Waybar custom module with `"return-type": "json"`.
+pub struct Waybar;
+
+#[derive(Serialize)]
+struct WaybarFormat<'a> {
+    text: &'a str,
+    tooltip: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    class: Option<&'a str>,
+}
+
+impl Emitter for Waybar {
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()> {
+        let output = WaybarFormat {
+            text: frame.text,
+            tooltip: frame.tooltip,
+            class: frame.class,
+        };
+
+        writeln!(out, "{}", sonic_rs::to_string(&output)?)?;
+
+        Ok(())
+    }
+}
+
+/// The i3bar/swaybar JSON protocol, see `swaybar-protocol(7)`.
+///
+/// The header and the opening bracket of the infinite array are written in
+/// [Emitter::start], then each frame is a status line with a single block,
+/// colored after its class with `[general] colors`.
+pub struct Swaybar {
+    pub colors: HashMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct SwaybarBlock<'a> {
+    name: &'a str,
+    full_text: &'a str,
+    markup: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<&'a str>,
+}
+
+impl Emitter for Swaybar {
+    fn start(&mut self, out: &mut dyn Write) -> Result<()> {
+        writeln!(out, "{{\"version\":1}}")?;
+        writeln!(out, "[")?;
+
+        Ok(())
+    }
+
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()> {
+        let block = SwaybarBlock {
+            name: "gpu_usage",
+            full_text: frame.text,
+            markup: "none",
+            color: class_color(&self.colors, frame),
+        };
+
+        writeln!(out, "[{}],", sonic_rs::to_string(&block)?)?;
+
+        Ok(())
+    }
+}
+
+/// i3blocks' line-based format: `full_text`, `short_text` and `color`.
+///
+/// i3blocks has no CSS, so the class is turned into a color with
+/// `[general] colors`. The color line is left empty when there's none.
+pub struct I3Blocks {
+    pub colors: HashMap<String, String>,
+}
+
+impl Emitter for I3Blocks {
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()> {
+        let color = class_color(&self.colors, frame).unwrap_or_default();
+
+        writeln!(out, "{}", frame.text)?;
+        writeln!(out, "{}", frame.text)?;
+        writeln!(out, "{color}")?;
+
+        Ok(())
+    }
+}
+
+/// Polybar `custom/script` module output.
+///
+/// Polybar parses `%{...}` as formatting tags, so any such sequence coming
+/// from the format string is escaped to be shown literally. The class is
+/// turned into a foreground color tag with `[general] colors`.
+pub struct Polybar {
+    pub colors: HashMap<String, String>,
+}
+
+impl Emitter for Polybar {
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()> {
+        let text = frame.text.replace("%{", "%%{");
+
+        match class_color(&self.colors, frame) {
+            Some(color) => writeln!(out, "%{{F{color}}}{text}%{{F-}}")?,
+            None => writeln!(out, "{text}")?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Yambar `script` module transactions: `tag|type|value` lines terminated by
+/// an empty line.
+pub struct Yambar;
+
+impl Emitter for Yambar {
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()> {
+        // Values are line-delimited, so multi-line tooltips are flattened.
+        writeln!(out, "text|string|{}", frame.text.replace('\n', " "))?;
+        writeln!(out, "tooltip|string|{}", frame.tooltip.replace('\n', " "))?;
+        writeln!(out, "powered_on|bool|{}", frame.data.powered_on)?;
+        writeln!(out, "busy|bool|{}", frame.data.has_running_processes)?;
+        writeln!(out)?;
+
+        Ok(())
+    }
+}
+
+/// One JSON object per line with the GPU state flags, for eww's `deflisten`.
+pub struct Eww;
+
+#[derive(Serialize)]
+struct EwwFormat<'a> {
+    text: &'a str,
+    tooltip: &'a str,
+    class: Option<&'a str>,
+    error: Option<&'a str>,
+    powered_on: bool,
+    busy: bool,
+}
+
+impl Emitter for Eww {
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()> {
+        let output = EwwFormat {
+            text: frame.text,
+            tooltip: frame.tooltip,
+            class: frame.class,
+            error: frame.error,
+            powered_on: frame.data.powered_on,
+            busy: frame.data.has_running_processes,
+        };
+
+        writeln!(out, "{}", sonic_rs::to_string(&output)?)?;
+
+        Ok(())
+    }
+}
+
+/// Every field of [GpuStatusData](crate::gpu_status::GpuStatusData) as typed
+/// JSON, ignoring the text and tooltip formats.
+///
+/// When the GPU can't be read, the line is `{"error": ..., "class": ...}`
+/// instead, so that it can't be mistaken for a powered off GPU.
+pub struct Raw;
+
+#[derive(Serialize)]
+struct RawError<'a> {
+    error: &'a str,
+    class: Option<&'a str>,
+}
+
+impl Emitter for Raw {
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()> {
+        let line = match frame.error {
+            Some(error) => sonic_rs::to_string(&RawError {
+                error,
+                class: frame.class,
+            })?,
+            None => sonic_rs::to_string(frame.data)?,
+        };
+
+        writeln!(out, "{line}")?;
+
+        Ok(())
+    }
+}
+
+/// Only the formatted text, for shell prompts and tmux.
+pub struct Plain;
+
+impl Emitter for Plain {
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()> {
+        writeln!(out, "{}", frame.text)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+pub mod emitters;
+
+use std::{collections::HashMap, io::Write};
+
+use clap::ValueEnum;
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+use strum::Display;
+
+use crate::{
+    gpu_status::GpuStatusData,
+    output::emitters::{Eww, I3Blocks, Plain, Polybar, Raw, Swaybar, Waybar, Yambar},
+};
+
+/// A single rendered sample handed to an [Emitter].
+pub struct Frame<'a> {
+    pub data: &'a GpuStatusData,
+    pub text: &'a str,
+    pub tooltip: &'a str,
+    /// CSS class for bars that support styling, e.g. the `[errors]` class.
+    pub class: Option<&'a str>,
+    /// The error that replaced the sample, `data` is then empty.
+    pub error: Option<&'a str>,
+}
+
+/// Writes [Frame]s in the protocol expected by a status bar.
+pub trait Emitter {
+    /// Called once before the first frame, e.g. to write a protocol header.
+    fn start(&mut self, _out: &mut dyn Write) -> Result<()> {
+        Ok(())
+    }
+
+    fn emit(&mut self, out: &mut dyn Write, frame: &Frame) -> Result<()>;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ValueEnum, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum OutputKind {
+    /// Waybar custom module JSON
+    #[default]
+    Waybar,
+    /// i3bar/swaybar JSON protocol
+    #[serde(alias = "i3bar")]
+    #[value(alias = "i3bar")]
+    Swaybar,
+    /// i3blocks full_text/short_text lines
+    I3blocks,
+    /// Polybar script module text
+    Polybar,
+    /// Yambar script module tags
+    Yambar,
+    /// JSON with state flags, suited for eww `deflisten`
+    Eww,
+    /// Every metric as typed JSON in SI base units
+    Raw,
+    /// Only the formatted text
+    Plain,
+}
+
+impl OutputKind {
+    /// Returns the emitter of this protocol, `colors` maps classes to colors
+    /// for bars without CSS.
+    pub fn emitter(self, colors: &HashMap<String, String>) -> Box<dyn Emitter> {
+        match self {
+            OutputKind::Waybar => Box::new(Waybar),
+            OutputKind::Swaybar => Box::new(Swaybar {
+                colors: colors.clone(),
+            }),
+            OutputKind::I3blocks => Box::new(I3Blocks {
+                colors: colors.clone(),
+            }),
+            OutputKind::Polybar => Box::new(Polybar {
+                colors: colors.clone(),
+            }),
+            OutputKind::Yambar => Box::new(Yambar),
+            OutputKind::Eww => Box::new(Eww),
+            OutputKind::Raw => Box::new(Raw),
+            OutputKind::Plain => Box::new(Plain),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_sysfs::gpu_handle::PerformanceLevel;
+    use uom::si::{
+        f32::{Information, Power},
+        information::{gibibyte, mebibyte},
+        power::watt,
+        thermodynamic_temperature::degree_celsius,
+    };
+
+    use super::*;
+    use crate::gpu_status::{PState, Temperature};
+
+    fn render(kind: OutputKind, data: &GpuStatusData) -> String {
+        render_text(kind, data, "50%|10%")
+    }
+
+    fn render_text(kind: OutputKind, data: &GpuStatusData, text: &str) -> String {
+        render_frame(
+            kind,
+            &Frame {
+                data,
+                text,
+                tooltip: "GPU: 50%\nMEM: 10%",
+                class: None,
+                error: None,
+            },
+        )
+    }
+
+    fn render_frame(kind: OutputKind, frame: &Frame) -> String {
+        let mut out = Vec::new();
+        let colors = HashMap::from([("hot".to_string(), "#FF0000".to_string())]);
+        let mut emitter = kind.emitter(&colors);
+        emitter.start(&mut out).unwrap();
+        emitter.emit(&mut out, frame).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    fn busy() -> GpuStatusData {
+        GpuStatusData {
+            powered_on: true,
+            has_running_processes: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_waybar() {
+        assert_eq!(
+            render(OutputKind::Waybar, &busy()),
+            "{\"text\":\"50%|10%\",\"tooltip\":\"GPU: 50%\\nMEM: 10%\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_waybar_class() {
+        let frame = Frame {
+            data: &GpuStatusData::default(),
+            text: "Error",
+            tooltip: "No supported GPU found",
+            class: Some("error"),
+            error: Some("No supported GPU found"),
+        };
+
+        assert_eq!(
+            render_frame(OutputKind::Waybar, &frame),
+            "{\"text\":\"Error\",\"tooltip\":\"No supported GPU found\",\"class\":\"error\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_swaybar() {
+        assert_eq!(
+            render(OutputKind::Swaybar, &busy()),
+            "{\"version\":1}\n[\n[{\"name\":\"gpu_usage\",\"full_text\":\"50%|10%\",\"markup\":\"none\"}],\n"
+        );
+    }
+
+    #[test]
+    fn test_i3blocks() {
+        assert_eq!(
+            render(OutputKind::I3blocks, &busy()),
+            "50%|10%\n50%|10%\n\n"
+        );
+    }
+
+    #[test]
+    fn test_i3blocks_color() {
+        let frame = Frame {
+            data: &busy(),
+            text: "95°C",
+            tooltip: "",
+            class: Some("hot"),
+            error: None,
+        };
+
+        assert_eq!(
+            render_frame(OutputKind::I3blocks, &frame),
+            "95°C\n95°C\n#FF0000\n"
+        );
+    }
+
+    #[test]
+    fn test_swaybar_color() {
+        let frame = Frame {
+            data: &busy(),
+            text: "95°C",
+            tooltip: "",
+            class: Some("hot"),
+            error: None,
+        };
+
+        assert_eq!(
+            render_frame(OutputKind::Swaybar, &frame),
+            "{\"version\":1}\n[\n[{\"name\":\"gpu_usage\",\"full_text\":\"95°C\",\"markup\":\"none\",\"color\":\"#FF0000\"}],\n"
+        );
+    }
+
+    #[test]
+    fn test_polybar() {
+        assert_eq!(render(OutputKind::Polybar, &busy()), "50%|10%\n");
+    }
+
+    /// Test that text that looks like a formatting tag is escaped.
+    #[test]
+    fn test_polybar_escapes_tags() {
+        assert_eq!(
+            render_text(OutputKind::Polybar, &busy(), "50%{F#fff}"),
+            "50%%{F#fff}\n"
+        );
+    }
+
+    #[test]
+    fn test_polybar_color() {
+        let frame = Frame {
+            data: &busy(),
+            text: "95°C",
+            tooltip: "",
+            class: Some("hot"),
+            error: None,
+        };
+
+        assert_eq!(
+            render_frame(OutputKind::Polybar, &frame),
+            "%{F#FF0000}95°C%{F-}\n"
+        );
+    }
+
+    #[test]
+    fn test_yambar() {
+        assert_eq!(
+            render(OutputKind::Yambar, &GpuStatusData::default()),
+            "text|string|50%|10%\ntooltip|string|GPU: 50% MEM: 10%\npowered_on|bool|false\nbusy|bool|false\n\n"
+        );
+    }
+
+    #[test]
+    fn test_eww() {
+        assert_eq!(
+            render(OutputKind::Eww, &busy()),
+            "{\"text\":\"50%|10%\",\"tooltip\":\"GPU: 50%\\nMEM: 10%\",\"class\":null,\"error\":null,\"powered_on\":true,\"busy\":true}\n"
+        );
+    }
+
+    #[test]
+    fn test_raw() {
+        let data = GpuStatusData {
+            gpu_utilization: Some(50),
+            mem_used: Some(Information::new::<mebibyte>(1.0)),
+            mem_total: Some(Information::new::<gibibyte>(8.0)),
+            temperature: Some(Temperature::new::<degree_celsius>(50.0)),
+            power: Some(Power::new::<watt>(120.0)),
+            p_state: Some(PState::P2),
+            p_level: Some(PerformanceLevel::Auto),
+            ..busy()
+        };
+
+        assert_eq!(
+            render(OutputKind::Raw, &data),
+            "{\"has_running_processes\":true,\"powered_on\":true,\"gpu_utilization_percent\":50,\
+             \"mem_used_bytes\":1048576,\"mem_total_bytes\":8589934592,\"gtt_used_bytes\":null,\
+             \"gtt_total_bytes\":null,\"vis_vram_used_bytes\":null,\"mem_rw_percent\":null,\
+             \"decoder_utilization_percent\":null,\"encoder_utilization_percent\":null,\
+             \"temperature_kelvin\":323.15,\"temperature_hotspot_kelvin\":null,\
+             \"temperature_mem_kelvin\":null,\"power_watts\":120.0,\"p_state\":\"P2\",\
+             \"p_level\":\"auto\",\"fan_speed_percent\":null,\"tx_bytes_per_second\":null,\
+             \"rx_bytes_per_second\":null,\"pcie_gen\":null,\"pcie_width\":null,\
+             \"pcie_max_gen\":null,\"pcie_max_width\":null,\"energy_joules\":null,\"cost\":null,\"processes\":null}\n"
+        );
+    }
+
+    /// Test that an error can't be mistaken for a powered off GPU.
+    #[test]
+    fn test_raw_error() {
+        let frame = Frame {
+            data: &GpuStatusData::default(),
+            text: "Error",
+            tooltip: "No supported GPU found",
+            class: Some("error"),
+            error: Some("No supported GPU found"),
+        };
+
+        assert_eq!(
+            render_frame(OutputKind::Raw, &frame),
+            "{\"error\":\"No supported GPU found\",\"class\":\"error\"}\n"
+        );
+    }
+}
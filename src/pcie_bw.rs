@@ -0,0 +1,126 @@
+//! PCIe throughput of amdgpu from the `pcie_bw` sysfs file.
+//!
+//! Reading `pcie_bw` makes the driver count the packets sent and received
+//! over [WINDOW], so each read blocks for that long. It is read in the
+//! background to keep the samples on time.
+
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use uom::si::{f32::Information, information::byte};
+
+use crate::fs_root::FsRoot;
+
+/// How long the driver counts packets for on each read.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Bytes sent and received per second.
+type Throughput = (Information, Information);
+
+/// Parses `pcie_bw`, `<received packets> <sent packets> <max payload size>`,
+/// into bytes sent and received per second.
+fn parse_pcie_bw(content: &str) -> Option<Throughput> {
+    let mut values = content.split_whitespace().map(|v| v.parse::<u64>().ok());
+    let (received, sent, max_payload) = (values.next()??, values.next()??, values.next()??);
+
+    // Packets may be smaller than the max payload, this is an upper bound.
+    let per_second = |packets: u64| {
+        Information::new::<byte>((packets * max_payload) as f32 / WINDOW.as_secs_f32())
+    };
+
+    Some((per_second(sent), per_second(received)))
+}
+
+/// The latest `pcie_bw` reading of a GPU, refreshed by a background thread
+/// that stops once this is dropped.
+pub struct PcieBandwidth {
+    latest: Arc<Mutex<Option<Throughput>>>,
+}
+
+impl PcieBandwidth {
+    /// Starts reading `pcie_bw` of the GPU at `bus_id`, or returns `None`
+    /// if it doesn't have one.
+    pub fn spawn(root: FsRoot, bus_id: String) -> Option<Self> {
+        let path = root.pci_device(&bus_id).join("pcie_bw");
+        if !path.exists() {
+            return None;
+        }
+
+        let latest = Arc::default();
+        let weak = Arc::downgrade(&latest);
+        thread::spawn(move || poll(&weak, &root, &bus_id, &path));
+
+        Some(Self { latest })
+    }
+
+    /// Returns the bytes sent and received per second over the last read,
+    /// `None` until the first read completes.
+    pub fn latest(&self) -> Option<Throughput> {
+        *self.latest.lock().unwrap()
+    }
+}
+
+fn read(root: &FsRoot, bus_id: &str, path: &Path) -> Option<Throughput> {
+    // Reading would wake up a suspended GPU.
+    if !root.is_powered_on(bus_id) {
+        return None;
+    }
+
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| parse_pcie_bw(&content))
+}
+
+fn poll(latest: &Weak<Mutex<Option<Throughput>>>, root: &FsRoot, bus_id: &str, path: &Path) {
+    loop {
+        let started = Instant::now();
+        let throughput = read(root, bus_id, path);
+
+        let Some(latest) = latest.upgrade() else {
+            return;
+        };
+        *latest.lock().unwrap() = throughput;
+        drop(latest);
+
+        // The read blocks for the window on real hardware, not in tests.
+        thread::sleep(WINDOW.saturating_sub(started.elapsed()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::information::kibibyte;
+
+    use super::*;
+    use crate::fs_root;
+
+    #[test]
+    fn test_parse_pcie_bw() {
+        let (tx, rx) = parse_pcie_bw("4096 1024 256\n").unwrap();
+        assert_eq!(tx.get::<kibibyte>(), 256.0);
+        assert_eq!(rx.get::<kibibyte>(), 1024.0);
+
+        assert!(parse_pcie_bw("4096 1024").is_none());
+    }
+
+    #[test]
+    fn test_spawn() {
+        assert!(PcieBandwidth::spawn(fs_root::fixture("amd-apu"), "0000:c4:00.0".into()).is_none());
+
+        let pcie_bw =
+            PcieBandwidth::spawn(fs_root::fixture("amd-dgpu"), "0000:03:00.0".into()).unwrap();
+        let (tx, rx) = loop {
+            if let Some(throughput) = pcie_bw.latest() {
+                break throughput;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(tx.get::<kibibyte>(), 64.0);
+        assert_eq!(rx.get::<kibibyte>(), 128.0);
+    }
+}
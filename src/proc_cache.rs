@@ -0,0 +1,206 @@
+//! Incremental scan of the processes using the GPU.
+//!
+//! Finding GPU users means opening the fd directory of every process, which
+//! adds up with thousands of processes. [ProcessCache] remembers the result
+//! per process, keyed by pid and start time so that a reused pid counts as a
+//! new process.
+
+use std::collections::HashMap;
+
+use color_eyre::eyre::Result;
+use procfs::process::{Process, all_processes_with_root};
+
+use crate::fs_root::FsRoot;
+
+/// Polls after which a process that didn't use the GPU is checked again, as
+/// it may have opened the GPU since.
+const RESCAN_AFTER: u32 = 10;
+
+struct CachedProcess {
+    start_time: u64,
+    name: String,
+    uses_gpu: bool,
+    /// The poll in which `uses_gpu` was computed.
+    checked_at: u32,
+}
+
+/// Remembers which processes use the GPU between polls.
+///
+/// New processes are checked when they show up and dead ones are dropped.
+/// Processes using the GPU are checked on every poll since they may let go
+/// of it at any time, the others only every [RESCAN_AFTER] polls. While no
+/// process uses the GPU, all of them are checked on every poll, so that the
+/// first one opening the GPU shows up right away.
+#[derive(Default)]
+pub struct ProcessCache {
+    processes: HashMap<i32, CachedProcess>,
+    polls: u32,
+    /// Whether the previous poll found any process using the GPU.
+    had_users: bool,
+}
+
+impl ProcessCache {
+    /// Returns the pid and name of the processes other than the current one
+    /// for which `uses_gpu` holds, sorted by pid.
+    pub fn scan(
+        &mut self,
+        root: &FsRoot,
+        mut uses_gpu: impl FnMut(&Process) -> bool,
+    ) -> Result<Vec<(i32, String)>> {
+        let procs = all_processes_with_root(root.proc())?;
+        let poll = self.polls;
+        self.polls = self.polls.wrapping_add(1);
+        let had_users = self.had_users;
+
+        let mut processes = HashMap::with_capacity(self.processes.len());
+        let mut users = Vec::new();
+
+        for proc in procs.flatten() {
+            if proc.pid == std::process::id() as i32 {
+                continue;
+            }
+            // The process exited while scanning.
+            let Ok(stat) = proc.stat() else {
+                continue;
+            };
+
+            let cached = self
+                .processes
+                .remove(&proc.pid)
+                .filter(|cached| cached.start_time == stat.starttime)
+                .filter(|cached| {
+                    !cached.uses_gpu
+                        && had_users
+                        && poll.wrapping_sub(cached.checked_at) < RESCAN_AFTER
+                });
+
+            let cached = cached.unwrap_or_else(|| CachedProcess {
+                start_time: stat.starttime,
+                name: stat.comm,
+                uses_gpu: uses_gpu(&proc),
+                checked_at: poll,
+            });
+
+            if cached.uses_gpu {
+                users.push((proc.pid, cached.name.clone()));
+            }
+            processes.insert(proc.pid, cached);
+        }
+
+        // Whatever wasn't seen again is dead.
+        self.processes = processes;
+        self.had_users = !users.is_empty();
+        users.sort_unstable_by_key(|(pid, _)| *pid);
+
+        Ok(users)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::symlink, path::Path};
+
+    use procfs::process::FDTarget;
+
+    use super::*;
+    use crate::fs_root::{temp_dir, write_stat};
+
+    fn uses_nvidia(proc: &Process) -> bool {
+        proc.fd().is_ok_and(|mut fds| {
+            fds.any(|fd| {
+                fd.is_ok_and(|fd| matches!(fd.target, FDTarget::Path(ref p) if p == "/dev/nvidia0"))
+            })
+        })
+    }
+
+    /// Writes a process with `fds` file descriptors, the last of which is
+    /// `/dev/nvidia0` if `uses_gpu`.
+    fn write_process(root: &Path, pid: i32, start_time: u64, fds: u32, uses_gpu: bool) {
+        let dir = root.join("proc").join(pid.to_string());
+        let _ = fs::remove_dir_all(&dir);
+        write_stat(root, pid, "app", start_time);
+
+        fs::create_dir_all(dir.join("fd")).unwrap();
+        for fd in 0..fds {
+            let target = if uses_gpu && fd == fds - 1 {
+                "/dev/nvidia0"
+            } else {
+                "/dev/null"
+            };
+            symlink(target, dir.join("fd").join(fd.to_string())).unwrap();
+        }
+    }
+
+    fn pids(users: Vec<(i32, String)>) -> Vec<i32> {
+        users.into_iter().map(|(pid, _)| pid).collect()
+    }
+
+    /// Test that processes starting, stopping and reusing a pid are picked
+    /// up, and that known processes aren't checked again.
+    #[test]
+    fn test_scan() {
+        let dir = temp_dir("proc-cache");
+        let root = FsRoot::new(dir.clone());
+        write_process(&dir, 100, 1, 2, true);
+        write_process(&dir, 101, 1, 2, false);
+
+        let mut cache = ProcessCache::default();
+        assert_eq!(pids(cache.scan(&root, uses_nvidia).unwrap()), [100]);
+
+        // A known process opening the GPU is only noticed on a rescan.
+        write_process(&dir, 101, 1, 2, true);
+        assert_eq!(pids(cache.scan(&root, uses_nvidia).unwrap()), [100]);
+
+        // The pid of an exited process is reused.
+        write_process(&dir, 101, 2, 2, true);
+        write_process(&dir, 102, 2, 2, true);
+        assert_eq!(
+            pids(cache.scan(&root, uses_nvidia).unwrap()),
+            [100, 101, 102]
+        );
+
+        // Users are checked on every poll.
+        write_process(&dir, 100, 1, 2, false);
+        fs::remove_dir_all(dir.join("proc/102")).unwrap();
+        assert_eq!(pids(cache.scan(&root, uses_nvidia).unwrap()), [101]);
+        assert_eq!(cache.processes.len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rescan() {
+        let dir = temp_dir("proc-cache-rescan");
+        let root = FsRoot::new(dir.clone());
+        write_process(&dir, 100, 1, 2, true);
+        write_process(&dir, 101, 1, 2, false);
+
+        let mut cache = ProcessCache::default();
+        assert_eq!(pids(cache.scan(&root, uses_nvidia).unwrap()), [100]);
+
+        write_process(&dir, 101, 1, 2, true);
+        let polls = (1..=RESCAN_AFTER)
+            .position(|_| cache.scan(&root, uses_nvidia).unwrap().len() == 2)
+            .unwrap();
+        assert_eq!(polls as u32, RESCAN_AFTER - 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Test that a known process opening the GPU shows up on the next poll
+    /// while no process uses the GPU.
+    #[test]
+    fn test_rescan_without_users() {
+        let dir = temp_dir("proc-cache-rescan-without-users");
+        let root = FsRoot::new(dir.clone());
+        write_process(&dir, 100, 1, 2, false);
+
+        let mut cache = ProcessCache::default();
+        assert!(cache.scan(&root, uses_nvidia).unwrap().is_empty());
+
+        write_process(&dir, 100, 1, 2, true);
+        assert_eq!(pids(cache.scan(&root, uses_nvidia).unwrap()), [100]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
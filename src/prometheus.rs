@@ -0,0 +1,199 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use color_eyre::eyre::{Result, eyre};
+use sonic_rs::{JsonContainerTrait, JsonValueTrait};
+
+use crate::{
+    energy::EnergyMeter,
+    gpu_status::{DeviceInfo, GpuStatus, GpuStatusData},
+};
+
+const METRIC_PREFIX: &str = "gpu_";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// How long a client may take to send its request or read the response, so
+/// that a stalled client doesn't block the following scrapes.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves [GpuStatusData] as Prometheus gauges on `GET /metrics`.
+///
+/// Each scrape calls [GpuStatus::compute], so the power gating of the backend
+/// applies and an idle NVIDIA GPU is not woken up by scrapes. The energy is
+/// added up across scrapes by `energy_meter`.
+pub fn serve(
+    gpu_status_handler: &dyn GpuStatus,
+    energy_meter: &mut EnergyMeter,
+    listen: SocketAddr,
+) -> Result<()> {
+    let listener = TcpListener::bind(listen)?;
+
+    eprintln!("Serving metrics on http://{listen}/metrics");
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Warning: failed to accept connection: {e}");
+                continue;
+            }
+        };
+
+        if let Err(e) = handle_connection(gpu_status_handler, energy_meter, stream) {
+            eprintln!("Warning: failed to handle request: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_connection(
+    gpu_status_handler: &dyn GpuStatus,
+    energy_meter: &mut EnergyMeter,
+    stream: TcpStream,
+) -> Result<()> {
+    stream.set_read_timeout(Some(STREAM_TIMEOUT))?;
+    stream.set_write_timeout(Some(STREAM_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain the headers, the body of a GET request is ignored.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+
+    let (status, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => match gpu_status_handler.compute() {
+            Ok(mut data) => {
+                energy_meter.update_now(&mut data);
+                (
+                    "200 OK",
+                    render_metrics(&data, &gpu_status_handler.device_info())?,
+                )
+            }
+            Err(e) => ("500 Internal Server Error", format!("{e}\n")),
+        },
+        (Some("GET"), Some(_)) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: \
+         close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    Ok(())
+}
+
+/// Renders `data` in the Prometheus text exposition format.
+///
+/// Metric names are derived from the keys of the serialized [GpuStatusData],
+/// so they carry their unit, e.g. `gpu_power_watts`. Booleans are exported as
+/// `0`/`1`, enum values as an `_info` gauge with a `value` label, and
+/// unavailable values are omitted, as are arrays such as `processes`.
+/// Metrics are sorted by name.
+///
+/// The `gpu` label is always `"0"` since a process watches a single GPU,
+/// `pci_bus` is what tells GPUs apart.
+pub fn render_metrics(data: &GpuStatusData, device_info: &DeviceInfo) -> Result<String> {
+    let value = sonic_rs::to_value(data)?;
+    let object = value
+        .as_object()
+        .ok_or(eyre!("GPU status did not serialize to an object"))?;
+
+    let labels = format!(
+        "gpu=\"0\",vendor=\"{}\",pci_bus=\"{}\"",
+        escape_label(&device_info.vendor),
+        escape_label(device_info.pci_bus_id.as_deref().unwrap_or_default())
+    );
+
+    let mut entries: Vec<_> = object.iter().collect();
+    entries.sort_unstable_by_key(|(key, _)| *key);
+
+    let mut out = String::new();
+
+    for (key, value) in entries {
+        let name = format!("{METRIC_PREFIX}{}", key.trim_start_matches(METRIC_PREFIX));
+
+        if let Some(v) = value.as_bool() {
+            writeln!(out, "# TYPE {name} gauge")?;
+            writeln!(out, "{name}{{{labels}}} {}", u8::from(v))?;
+        } else if let Some(v) = value.as_f64() {
+            writeln!(out, "# TYPE {name} gauge")?;
+            writeln!(out, "{name}{{{labels}}} {v}")?;
+        } else if let Some(v) = value.as_str() {
+            writeln!(out, "# TYPE {name}_info gauge")?;
+            writeln!(
+                out,
+                "{name}_info{{{labels},value=\"{}\"}} 1",
+                escape_label(v)
+            )?;
+        }
+    }
+
+    Ok(out)
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::{f32::Power, power::watt};
+
+    use super::*;
+    use crate::gpu_status::PState;
+
+    #[test]
+    fn test_render_metrics() {
+        let data = GpuStatusData {
+            powered_on: true,
+            has_running_processes: false,
+            gpu_utilization: Some(42),
+            power: Some(Power::new::<watt>(120.5)),
+            p_state: Some(PState::P8),
+            ..Default::default()
+        };
+        let device_info = DeviceInfo {
+            vendor: "nvidia".to_string(),
+            pci_bus_id: Some("0000:01:00.0".to_string()),
+            ..Default::default()
+        };
+
+        let labels = r#"gpu="0",vendor="nvidia",pci_bus="0000:01:00.0""#;
+
+        assert_eq!(
+            render_metrics(&data, &device_info).unwrap(),
+            format!(
+                "# TYPE gpu_utilization_percent gauge
+gpu_utilization_percent{{{labels}}} 42
+# TYPE gpu_has_running_processes gauge
+gpu_has_running_processes{{{labels}}} 0
+# TYPE gpu_p_state_info gauge
+gpu_p_state_info{{{labels},value=\"P8\"}} 1
+# TYPE gpu_power_watts gauge
+gpu_power_watts{{{labels}}} 120.5
+# TYPE gpu_powered_on gauge
+gpu_powered_on{{{labels}}} 1
+"
+            )
+        );
+    }
+}
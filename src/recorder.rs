@@ -0,0 +1,615 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre::{Result, WrapErr, eyre};
+use humantime_serde::re::humantime;
+use serde::{Deserialize, Serialize};
+use sonic_rs::{JsonContainerTrait, JsonValueTrait, Value};
+
+use crate::{
+    config::structs::LoggingConfig,
+    gpu_status::{DeviceInfo, GpuStatusData},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordFormat {
+    /// One [Record] as JSON per line, after a [DeviceLine] whenever the GPU
+    /// changes.
+    #[default]
+    Jsonl,
+    /// A `timestamp` column followed by the [GpuStatusData] keys sorted by
+    /// name. Unavailable values are empty.
+    Csv,
+}
+
+impl RecordFormat {
+    /// Returns [RecordFormat::Csv] for `.csv` files, [RecordFormat::Jsonl]
+    /// otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => RecordFormat::Csv,
+            _ => RecordFormat::Jsonl,
+        }
+    }
+}
+
+/// A timestamped sample, as written by [Recorder].
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    #[serde(with = "humantime_serde")]
+    pub timestamp: SystemTime,
+    #[serde(flatten)]
+    pub data: GpuStatusData,
+}
+
+/// The identity of the GPU the following records were sampled from, written
+/// once per file rather than in every record.
+#[derive(Serialize, Deserialize)]
+struct DeviceLine {
+    device: DeviceInfo,
+}
+
+/// A line of a JSON Lines file. [DeviceLine] comes first since a [Record]
+/// doesn't deny its unknown keys.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonlLine {
+    Device(DeviceLine),
+    Record(Record),
+}
+
+/// The samples of a file written by [Recorder].
+pub struct Trace {
+    /// The first GPU of the file, `None` for CSV files which don't record it.
+    pub device: Option<DeviceInfo>,
+    pub records: Vec<Record>,
+}
+
+/// Appends samples to a file, rotating it by size or age.
+///
+/// Rotated files get a numeric suffix, `samples.jsonl.1` being the newest.
+pub struct Recorder {
+    path: PathBuf,
+    format: RecordFormat,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+    file: BufWriter<File>,
+    size: u64,
+    created: SystemTime,
+    /// The identity last written to the current file.
+    device: Option<DeviceInfo>,
+}
+
+impl Recorder {
+    /// Returns `None` if no `path` is configured.
+    pub fn new(config: &LoggingConfig) -> Result<Option<Self>> {
+        let Some(ref path) = config.path else {
+            return Ok(None);
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let (file, size, created) = open(path)?;
+
+        let mut recorder = Self {
+            path: path.clone(),
+            format: config.format,
+            max_size: config.max_size,
+            max_age: config.max_age,
+            keep: config.keep,
+            file,
+            size,
+            created,
+            device: None,
+        };
+
+        if size == 0 {
+            recorder.write_header()?;
+        } else if !recorder.has_current_header()? {
+            // Rows with the new columns would not match the old header.
+            recorder.rotate()?;
+        }
+
+        Ok(Some(recorder))
+    }
+
+    pub fn record(
+        &mut self,
+        data: &GpuStatusData,
+        device: &DeviceInfo,
+        timestamp: SystemTime,
+    ) -> Result<()> {
+        if self.needs_rotation(timestamp) {
+            self.rotate()?;
+        }
+
+        if self.format == RecordFormat::Jsonl && self.device.as_ref() != Some(device) {
+            self.write_line(&sonic_rs::to_string(&DeviceLine {
+                device: device.clone(),
+            })?)?;
+            self.device = Some(device.clone());
+        }
+
+        let line = match self.format {
+            RecordFormat::Jsonl => sonic_rs::to_string(&Record {
+                timestamp,
+                data: data.clone(),
+            })?,
+            RecordFormat::Csv => csv_row(data, timestamp)?,
+        };
+
+        self.write_line(&line)
+    }
+
+    fn needs_rotation(&self, now: SystemTime) -> bool {
+        let too_big = self.max_size.is_some_and(|max| self.size >= max);
+        let too_old = self
+            .max_age
+            .is_some_and(|max| now.duration_since(self.created).is_ok_and(|age| age >= max));
+
+        too_big || too_old
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.file.flush()?;
+
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated_path(&self.path, self.keep));
+
+            for n in (1..self.keep).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        (self.file, self.size, self.created) = open(&self.path)?;
+        self.device = None;
+
+        self.write_header()
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        match self.format {
+            RecordFormat::Jsonl => Ok(()),
+            RecordFormat::Csv => self.write_line(&csv_header()?),
+        }
+    }
+
+    /// Returns whether the file starts with the columns of this version,
+    /// always `true` for JSON Lines which have no header.
+    fn has_current_header(&self) -> Result<bool> {
+        match self.format {
+            RecordFormat::Jsonl => Ok(true),
+            RecordFormat::Csv => {
+                let mut header = String::new();
+                BufReader::new(File::open(&self.path)?).read_line(&mut header)?;
+
+                Ok(header.trim_end() == csv_header()?)
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        writeln!(self.file, "{line}")?;
+        self.file.flush()?;
+        self.size += line.len() as u64 + 1;
+
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> Result<(BufWriter<File>, u64, SystemTime)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let metadata = file.metadata()?;
+    let created = metadata.created().unwrap_or_else(|_| SystemTime::now());
+
+    Ok((BufWriter::new(file), metadata.len(), created))
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{n}"));
+    path.into()
+}
+
+fn csv_header() -> Result<String> {
+    let keys = csv_keys(&GpuStatusData::default())?.join(",");
+
+    Ok(format!("timestamp,{keys}"))
+}
+
+/// Returns the serialized [GpuStatusData] keys sorted by name.
+fn csv_keys(data: &GpuStatusData) -> Result<Vec<String>> {
+    let value = sonic_rs::to_value(data)?;
+    let object = value
+        .as_object()
+        .ok_or(eyre!("GPU status did not serialize to an object"))?;
+
+    let mut keys: Vec<String> = object.iter().map(|(k, _)| k.to_string()).collect();
+    keys.sort_unstable();
+
+    Ok(keys)
+}
+
+fn csv_row(data: &GpuStatusData, timestamp: SystemTime) -> Result<String> {
+    let value = sonic_rs::to_value(data)?;
+
+    let mut row = vec![humantime::format_rfc3339(timestamp).to_string()];
+
+    for key in csv_keys(data)? {
+        let field = &value[key.as_str()];
+
+        row.push(if let Some(v) = field.as_str() {
+            csv_escape(v)
+        } else if field.is_null() {
+            String::new()
+        } else {
+            // Lists like `processes` are written as JSON.
+            csv_escape(&field.to_string())
+        });
+    }
+
+    Ok(row.join(","))
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Reads a file written by [Recorder], guessing its format from the
+/// extension.
+pub fn read_trace(path: &Path) -> Result<Trace> {
+    let contents =
+        fs::read_to_string(path).wrap_err_with(|| format!("Unable to read {}", path.display()))?;
+
+    match RecordFormat::from_path(path) {
+        RecordFormat::Jsonl => parse_jsonl(&contents),
+        RecordFormat::Csv => Ok(Trace {
+            device: None,
+            records: parse_csv(&contents)?,
+        }),
+    }
+}
+
+fn parse_jsonl(contents: &str) -> Result<Trace> {
+    let mut trace = Trace {
+        device: None,
+        records: Vec::new(),
+    };
+
+    for (n, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match sonic_rs::from_str(line)
+            .wrap_err_with(|| format!("Invalid record on line {}", n + 1))?
+        {
+            JsonlLine::Device(DeviceLine { device }) => {
+                trace.device.get_or_insert(device);
+            }
+            JsonlLine::Record(record) => trace.records.push(record),
+        }
+    }
+
+    Ok(trace)
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<Record>> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let (_, header) = lines.next().ok_or(eyre!("Missing CSV header"))?;
+    let keys = csv_split(header);
+
+    if keys.first().map(String::as_str) != Some("timestamp") {
+        return Err(eyre!("The first CSV column must be `timestamp`"));
+    }
+
+    lines
+        .map(|(n, line)| {
+            let cells = csv_split(line);
+
+            if cells.len() != keys.len() {
+                return Err(eyre!(
+                    "Expected {} columns on line {}, found {}",
+                    keys.len(),
+                    n + 1,
+                    cells.len()
+                ));
+            }
+
+            let timestamp = humantime::parse_rfc3339(&cells[0])
+                .wrap_err_with(|| format!("Invalid timestamp on line {}", n + 1))?;
+
+            let mut object = sonic_rs::Object::new();
+            for (key, cell) in keys.iter().zip(&cells).skip(1) {
+                if let Some(value) = csv_value(cell) {
+                    object.insert(key, value);
+                }
+            }
+
+            let data = sonic_rs::from_value(&object.into())
+                .wrap_err_with(|| format!("Invalid record on line {}", n + 1))?;
+
+            Ok(Record { timestamp, data })
+        })
+        .collect()
+}
+
+/// Splits a row written by [csv_row], undoing [csv_escape].
+fn csv_split(line: &str) -> Vec<String> {
+    let mut cells = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let cell = cells.last_mut().unwrap();
+
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(String::new()),
+            c => cell.push(c),
+        }
+    }
+
+    cells
+}
+
+/// Returns the JSON value of a cell, `None` for unavailable values.
+fn csv_value(cell: &str) -> Option<Value> {
+    if cell.is_empty() {
+        None
+    } else if cell.starts_with(['[', '{']) {
+        sonic_rs::from_str(cell).ok()
+    } else if let Ok(v) = cell.parse::<bool>() {
+        Some(Value::from(v))
+    } else if let Ok(v) = cell.parse::<u64>() {
+        Some(Value::from(v))
+    } else if let Ok(v) = cell.parse::<f64>() {
+        Value::new_f64(v)
+    } else {
+        Some(Value::from(cell))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uom::si::{f32::Information, information::mebibyte};
+
+    use super::*;
+    use crate::{
+        fs_root::temp_dir,
+        gpu_status::{GpuProcess, PState},
+    };
+
+    fn device() -> DeviceInfo {
+        DeviceInfo {
+            vendor: "nvidia".to_owned(),
+            name: Some("NVIDIA GeForce RTX 4070".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn sample() -> GpuStatusData {
+        GpuStatusData {
+            powered_on: true,
+            gpu_utilization: Some(42),
+            p_state: Some(PState::P2),
+            ..Default::default()
+        }
+    }
+
+    fn sample_with_processes() -> GpuStatusData {
+        GpuStatusData {
+            processes: Some(vec![GpuProcess {
+                pid: 42,
+                name: "Web Content, \"tab\"".to_string(),
+                vram: Some(Information::new::<mebibyte>(64.0)),
+                gpu_utilization: None,
+            }]),
+            ..sample()
+        }
+    }
+
+    #[test]
+    fn test_csv_row() {
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+
+        let header = csv_split(&csv_header().unwrap());
+        let row = csv_split(&csv_row(&sample(), timestamp).unwrap());
+        assert_eq!(header.len(), row.len());
+
+        let cell = |key: &str| {
+            let column = header.iter().position(|k| k == key).unwrap();
+            row[column].as_str()
+        };
+        assert_eq!(cell("timestamp"), "1970-01-01T00:01:00Z");
+        assert_eq!(cell("gpu_utilization_percent"), "42");
+        assert_eq!(cell("p_state"), "P2");
+        assert_eq!(cell("powered_on"), "true");
+        assert_eq!(cell("has_running_processes"), "false");
+        assert_eq!(cell("temperature_kelvin"), "");
+
+        assert!(header[1..].is_sorted());
+    }
+
+    /// Test that the file rotates by size and only `keep` files are kept.
+    #[test]
+    fn test_rotation_by_size() {
+        let dir = temp_dir("rotation");
+        let path = dir.join("samples.jsonl");
+
+        let config = LoggingConfig {
+            path: Some(path.clone()),
+            max_size: Some(1),
+            keep: 2,
+            ..Default::default()
+        };
+        let mut recorder = Recorder::new(&config).unwrap().unwrap();
+
+        for _ in 0..4 {
+            recorder
+                .record(&sample(), &device(), SystemTime::now())
+                .unwrap();
+        }
+
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            ["samples.jsonl", "samples.jsonl.1", "samples.jsonl.2"]
+        );
+
+        // A new file starts with the identity again.
+        let trace = read_trace(&path).unwrap();
+        assert_eq!(trace.device, Some(device()));
+        assert_eq!(trace.records.len(), 1);
+        assert_eq!(trace.records[0].data.gpu_utilization, Some(42));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv_header() {
+        let dir = temp_dir("csv");
+        let path = dir.join("samples.csv");
+
+        let config = LoggingConfig {
+            path: Some(path.clone()),
+            format: RecordFormat::Csv,
+            ..Default::default()
+        };
+        let mut recorder = Recorder::new(&config).unwrap().unwrap();
+        recorder
+            .record(&sample(), &device(), SystemTime::now())
+            .unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let mut lines = contents.lines();
+        assert!(
+            lines
+                .next()
+                .unwrap()
+                .starts_with("timestamp,cost,decoder_utilization_percent,")
+        );
+        assert_eq!(lines.count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Test that a CSV file written with other columns is rotated rather than
+    /// appended to.
+    #[test]
+    fn test_csv_schema_change() {
+        let dir = temp_dir("csv-schema");
+        let path = dir.join("samples.csv");
+        fs::write(
+            &path,
+            "timestamp,gpu_utilization_percent\n1970-01-01T00:01:00Z,42\n",
+        )
+        .unwrap();
+
+        let config = LoggingConfig {
+            path: Some(path.clone()),
+            format: RecordFormat::Csv,
+            ..Default::default()
+        };
+        let mut recorder = Recorder::new(&config).unwrap().unwrap();
+        recorder
+            .record(&sample(), &device(), SystemTime::now())
+            .unwrap();
+
+        assert_eq!(read_trace(&path).unwrap().records.len(), 1);
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "timestamp,gpu_utilization_percent\n1970-01-01T00:01:00Z,42\n"
+        );
+
+        // Reopening with the same columns appends.
+        drop(recorder);
+        let mut recorder = Recorder::new(&config).unwrap().unwrap();
+        recorder
+            .record(&sample(), &device(), SystemTime::now())
+            .unwrap();
+        assert_eq!(read_trace(&path).unwrap().records.len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Test that both formats read back what [Recorder] wrote.
+    #[test]
+    fn test_read_trace_round_trip() {
+        let dir = temp_dir("read");
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(60);
+
+        for format in [RecordFormat::Jsonl, RecordFormat::Csv] {
+            let path = dir.join(match format {
+                RecordFormat::Jsonl => "samples.jsonl",
+                RecordFormat::Csv => "samples.csv",
+            });
+
+            let config = LoggingConfig {
+                path: Some(path.clone()),
+                format,
+                ..Default::default()
+            };
+            let mut recorder = Recorder::new(&config).unwrap().unwrap();
+            recorder.record(&sample(), &device(), timestamp).unwrap();
+            recorder
+                .record(&sample_with_processes(), &device(), timestamp)
+                .unwrap();
+
+            let Trace {
+                device: read,
+                records,
+            } = read_trace(&path).unwrap();
+            assert_eq!(
+                read,
+                (format == RecordFormat::Jsonl).then(device),
+                "{format:?}"
+            );
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0].timestamp, timestamp);
+            assert_eq!(
+                sonic_rs::to_string(&records[0].data).unwrap(),
+                sonic_rs::to_string(&sample()).unwrap()
+            );
+            assert_eq!(records[1].data.processes, sample_with_processes().processes);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv_split() {
+        assert_eq!(csv_split(r#"a,,"b,c","d""e""#), ["a", "", "b,c", r#"d"e"#]);
+    }
+}
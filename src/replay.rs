@@ -0,0 +1,256 @@
+use std::{cell::Cell, path::Path, sync::Arc, thread, time::Duration};
+
+use color_eyre::eyre::{Report, Result, eyre};
+
+use crate::{
+    gpu_status::{DeviceInfo, EndOfSamples, GpuStatus, GpuStatusData},
+    recorder::{self, Record, Trace},
+};
+
+/// A [GpuStatus] that plays back a trace written by `[logging]`.
+///
+/// Samples are spaced by the difference of their timestamps divided by
+/// `speed`, and the trace starts over once it reaches the end. A `speed` of
+/// 0 plays it back once without any delay, then returns [EndOfSamples].
+///
+/// The identity is the first one of the trace, or the `replay` vendor for
+/// CSV traces which don't record it.
+pub struct Replay {
+    records: Vec<Record>,
+    speed: f64,
+    position: Cell<usize>,
+    device_info: Arc<DeviceInfo>,
+}
+
+impl Replay {
+    pub fn open(path: &Path, speed: f64) -> Result<Self> {
+        Self::new(recorder::read_trace(path)?, speed)
+    }
+
+    fn new(Trace { device, records }: Trace, speed: f64) -> Result<Self> {
+        if records.is_empty() {
+            return Err(eyre!("The trace has no samples"));
+        }
+
+        Ok(Self {
+            records,
+            speed,
+            position: Cell::new(0),
+            device_info: Arc::new(device.unwrap_or_else(|| DeviceInfo {
+                vendor: "replay".to_string(),
+                ..Default::default()
+            })),
+        })
+    }
+
+    /// Returns how long to wait before the sample at `index`.
+    fn delay(&self, index: usize) -> Duration {
+        // Starting over waits as long as the first step of the trace.
+        let (previous, next) = match index {
+            0 if self.records.len() > 1 => (&self.records[0], &self.records[1]),
+            0 => return Duration::ZERO,
+            _ => (&self.records[index - 1], &self.records[index]),
+        };
+
+        next.timestamp
+            .duration_since(previous.timestamp)
+            .unwrap_or_default()
+    }
+}
+
+impl GpuStatus for Replay {
+    fn compute(&self) -> Result<GpuStatusData> {
+        let position = self.position.get();
+        let index = position % self.records.len();
+
+        // Starting over without delays would spin, flooding the output.
+        if self.speed == 0.0 && position == self.records.len() {
+            return Err(Report::new(EndOfSamples));
+        }
+        self.position.set(position + 1);
+
+        if position > 0 && self.speed > 0.0 {
+            thread::sleep(self.delay(index).div_f64(self.speed));
+        }
+
+        Ok(self.records[index].data.clone())
+    }
+
+    /// Returns the first sample with running processes, or the first sample
+    /// if the GPU is never busy in the trace.
+    fn compute_force(&self) -> Result<GpuStatusData> {
+        let record = self
+            .records
+            .iter()
+            .find(|r| r.data.powered_on && r.data.has_running_processes)
+            .unwrap_or(&self.records[0]);
+
+        Ok(record.data.clone())
+    }
+
+    fn device_info(&self) -> Arc<DeviceInfo> {
+        Arc::clone(&self.device_info)
+    }
+
+    fn is_hardware(&self) -> bool {
+        false
+    }
+
+    fn paces_samples(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, path::PathBuf, time::Instant};
+
+    use super::*;
+    use crate::{
+        formatter::State,
+        fs_root::{self, FsRoot},
+        output::Frame,
+        output::OutputKind,
+        resilience::Resilient,
+    };
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name)
+    }
+
+    /// Renders the first `n` samples of the trace with the Waybar emitter.
+    fn render(replay: &Replay, n: usize) -> String {
+        let mut text_state =
+            State::try_from_format("{gpu_utilization}% {temperature:c}°C").unwrap();
+        let mut tooltip_state =
+            State::try_from_format("MEM {mem_used:MiB}/{mem_total:MiB} MiB").unwrap();
+
+        let mut emitter = OutputKind::Waybar.emitter(&HashMap::new());
+        let mut out = Vec::new();
+
+        let device = replay.device_info();
+
+        for _ in 0..n {
+            let data = replay.compute().unwrap();
+            let frame = Frame {
+                data: &data,
+                text: data.get_text(&device, &mut text_state),
+                tooltip: data.get_tooltip(&device, &mut tooltip_state),
+                class: None,
+                error: None,
+            };
+            emitter.emit(&mut out, &frame).unwrap();
+        }
+
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Test the whole formatting and output path against a recorded trace,
+    /// starting over at its end.
+    #[test]
+    fn test_replay_renders_trace() {
+        for name in ["trace.jsonl", "trace.csv"] {
+            // Fast enough not to slow the test down, but still starting over.
+            let replay = Replay::open(&fixture(name), 1000.0).unwrap();
+
+            assert_eq!(
+                render(&replay, 4),
+                concat!(
+                    r#"{"text":"Off","tooltip":"GPU powered off"}"#,
+                    "\n",
+                    r#"{"text":"Idle","tooltip":"GPU idle"}"#,
+                    "\n",
+                    r#"{"text":"87% 65°C","tooltip":"MEM 2048/8192 MiB"}"#,
+                    "\n",
+                    r#"{"text":"Off","tooltip":"GPU powered off"}"#,
+                    "\n",
+                ),
+                "{name}"
+            );
+        }
+    }
+
+    /// Test that the identity comes from the trace when it records one.
+    #[test]
+    fn test_device_info() {
+        let replay = Replay::open(&fixture("trace.jsonl"), 0.0).unwrap();
+        let device = replay.device_info();
+        assert_eq!(device.vendor, "nvidia");
+        assert_eq!(device.name.as_deref(), Some("NVIDIA GeForce RTX 4070"));
+
+        let replay = Replay::open(&fixture("trace.csv"), 0.0).unwrap();
+        assert_eq!(replay.device_info().vendor, "replay");
+    }
+
+    /// Test that a trace recorded for a PCI device absent from this machine
+    /// isn't reported as disconnected.
+    #[test]
+    fn test_foreign_pci_device() {
+        let dir = fs_root::temp_dir("replay-pci");
+        let path = dir.join("trace.jsonl");
+        fs::write(
+            &path,
+            concat!(
+                r#"{"device":{"vendor":"amd","pci_bus_id":"0000:99:00.0"}}"#,
+                "\n",
+                r#"{"timestamp":"2026-01-01T12:00:00Z","has_running_processes":false,"powered_on":true}"#,
+                "\n",
+            ),
+        )
+        .unwrap();
+
+        let resilient = Resilient::new(
+            {
+                let path = path.clone();
+                Box::new(move || Ok(Box::new(Replay::open(&path, 1.0)?)))
+            },
+            FsRoot::new(dir.clone()),
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        );
+
+        assert!(resilient.compute().unwrap().powered_on);
+        assert!(resilient.compute().unwrap().powered_on);
+        assert!(!resilient.is_hardware());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compute_force_prefers_busy_sample() {
+        let replay = Replay::open(&fixture("trace.jsonl"), 0.0).unwrap();
+
+        assert_eq!(replay.compute_force().unwrap().gpu_utilization, Some(87));
+    }
+
+    /// Test that a trace played without delays stops at its end.
+    #[test]
+    fn test_end_without_delays() {
+        let replay = Replay::open(&fixture("trace.jsonl"), 0.0).unwrap();
+
+        for _ in 0..3 {
+            replay.compute().unwrap();
+        }
+
+        let Err(error) = replay.compute() else {
+            panic!("expected the end of the trace");
+        };
+        assert!(error.downcast_ref::<EndOfSamples>().is_some());
+    }
+
+    /// Test that samples are spaced by their timestamps divided by the speed.
+    #[test]
+    fn test_speed() {
+        // The fixture spans 2 seconds.
+        let replay = Replay::open(&fixture("trace.jsonl"), 20.0).unwrap();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            replay.compute().unwrap();
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    cell::{Cell, RefCell},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{Report, Result, eyre};
+
+use crate::{
+    formatter::fields::MemField,
+    fs_root::FsRoot,
+    gpu_status::{DeviceInfo, DeviceLost, Disconnected, EndOfSamples, GpuStatus, GpuStatusData},
+};
+
+/// Consecutive failures after which the backend is initialized again even
+/// if it didn't report [DeviceLost].
+const REINIT_AFTER: u32 = 3;
+
+pub type Build = Box<dyn Fn() -> Result<Box<dyn GpuStatus>>>;
+
+/// A [GpuStatus] that survives backend errors.
+///
+/// The backend is built lazily with `build`. After an error, the next
+/// attempts are delayed with an exponential backoff, from `initial_backoff`
+/// up to `max_backoff`, and return the last error in the meantime. The backend
+/// is built again when it reports [DeviceLost] or keeps failing, so a driver
+/// reload doesn't require a restart.
+///
+/// DRM cards are watched for hot-plugging, e.g. of an eGPU. While the PCI
+/// device of the backend is absent, [Disconnected] is returned without
+/// retrying. When cards appear or disappear, the backend is built again.
+pub struct Resilient {
+    build: Build,
+    root: FsRoot,
+    handler: RefCell<Option<Box<dyn GpuStatus>>>,
+    backoff: RefCell<Backoff>,
+    /// Info of the last backend built.
+    device_info: RefCell<Arc<DeviceInfo>>,
+    /// Whether the last backend built reads a GPU of this machine.
+    is_hardware: Cell<bool>,
+    cards: RefCell<Vec<String>>,
+}
+
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+    retry_at: Option<Instant>,
+    last_error: String,
+}
+
+impl Backoff {
+    fn delay(&self) -> Duration {
+        let factor = 2u32.saturating_pow(self.failures.saturating_sub(1));
+
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+
+    fn reset(&mut self) {
+        self.failures = 0;
+        self.retry_at = None;
+    }
+}
+
+impl Resilient {
+    pub fn new(
+        build: Build,
+        root: FsRoot,
+        initial_backoff: Duration,
+        max_backoff: Duration,
+    ) -> Self {
+        Self {
+            build,
+            cards: RefCell::new(root.drm_cards()),
+            root,
+            handler: RefCell::new(None),
+            backoff: RefCell::new(Backoff {
+                initial: initial_backoff,
+                max: max_backoff,
+                failures: 0,
+                retry_at: None,
+                last_error: String::new(),
+            }),
+            device_info: RefCell::default(),
+            is_hardware: Cell::new(false),
+        }
+    }
+
+    fn with_handler<T>(&self, f: impl FnOnce(&dyn GpuStatus) -> Result<T>) -> Result<T> {
+        let mut handler = self.handler.borrow_mut();
+
+        let handler = match *handler {
+            Some(ref handler) => handler,
+            None => {
+                let built = (self.build)()?;
+                *self.device_info.borrow_mut() = built.device_info();
+                self.is_hardware.set(built.is_hardware());
+                handler.insert(built)
+            }
+        };
+
+        f(handler.as_ref())
+    }
+
+    /// Drops the backend when cards appeared or disappeared, and returns
+    /// [Disconnected] while the PCI device of the last backend is absent.
+    fn watch_cards(&self) -> Result<()> {
+        let cards = self.root.drm_cards();
+        let changed = *self.cards.borrow() != cards;
+        *self.cards.borrow_mut() = cards;
+
+        // Only backends reading a PCI device of this machine are affected,
+        // not e.g. replays of a trace recorded elsewhere.
+        let pci_bus_id = self
+            .is_hardware
+            .get()
+            .then(|| self.device_info.borrow().pci_bus_id.clone())
+            .flatten();
+        if let Some(pci_bus_id) = pci_bus_id {
+            if !self.root.pci_device(&pci_bus_id).exists() {
+                *self.handler.borrow_mut() = None;
+
+                return Err(Report::new(Disconnected {
+                    pci_bus_id: pci_bus_id.clone(),
+                }));
+            }
+
+            if changed {
+                *self.handler.borrow_mut() = None;
+                self.backoff.borrow_mut().reset();
+            }
+        } else if changed && self.handler.borrow().is_none() {
+            // Try right away, the GPU may have been attached.
+            self.backoff.borrow_mut().reset();
+        }
+
+        Ok(())
+    }
+
+    fn compute_at(&self, now: Instant) -> Result<GpuStatusData> {
+        self.watch_cards()?;
+
+        {
+            let backoff = self.backoff.borrow();
+            if backoff.retry_at.is_some_and(|retry_at| now < retry_at) {
+                return Err(eyre!("{}", backoff.last_error));
+            }
+        }
+
+        let result = self.with_handler(|handler| handler.compute());
+        let mut backoff = self.backoff.borrow_mut();
+
+        match result {
+            Ok(data) => {
+                backoff.reset();
+                Ok(data)
+            }
+            // Not a failure, there's nothing to retry.
+            Err(e) if e.downcast_ref::<EndOfSamples>().is_some() => Err(e),
+            Err(e) => {
+                backoff.failures += 1;
+
+                if is_lost(&e) || backoff.failures >= REINIT_AFTER {
+                    *self.handler.borrow_mut() = None;
+                }
+
+                let delay = backoff.delay();
+                backoff.retry_at = Some(now + delay);
+                backoff.last_error = format!("{e:#}");
+
+                eprintln!("Warning: {e:#}, retrying in {delay:?}");
+
+                Err(e)
+            }
+        }
+    }
+}
+
+impl GpuStatus for Resilient {
+    fn compute(&self) -> Result<GpuStatusData> {
+        self.compute_at(Instant::now())
+    }
+
+    /// Propagates errors right away, without any backoff.
+    fn compute_force(&self) -> Result<GpuStatusData> {
+        self.with_handler(|handler| handler.compute_force())
+    }
+
+    fn device_info(&self) -> Arc<DeviceInfo> {
+        Arc::clone(&self.device_info.borrow())
+    }
+
+    fn background_fields(&self) -> Vec<MemField> {
+        self.handler
+            .borrow()
+            .as_ref()
+            .map(|handler| handler.background_fields())
+            .unwrap_or_default()
+    }
+
+    fn is_hardware(&self) -> bool {
+        self.is_hardware.get()
+    }
+
+    fn paces_samples(&self) -> bool {
+        self.handler
+            .borrow()
+            .as_ref()
+            .is_some_and(|handler| handler.paces_samples())
+    }
+}
+
+fn is_lost(error: &Report) -> bool {
+    error.downcast_ref::<DeviceLost>().is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, rc::Rc};
+
+    use super::*;
+    use crate::fs_root;
+
+    /// Fails while `failing` is set.
+    struct Flaky {
+        failing: Rc<Cell<bool>>,
+        lost: bool,
+        device_info: Arc<DeviceInfo>,
+    }
+
+    impl GpuStatus for Flaky {
+        fn compute(&self) -> Result<GpuStatusData> {
+            match (self.failing.get(), self.lost) {
+                (false, _) => Ok(GpuStatusData {
+                    powered_on: true,
+                    ..Default::default()
+                }),
+                (true, false) => Err(eyre!("Failed to read the sensors")),
+                (true, true) => Err(Report::new(DeviceLost)),
+            }
+        }
+
+        fn device_info(&self) -> Arc<DeviceInfo> {
+            Arc::clone(&self.device_info)
+        }
+    }
+
+    /// Returns a [Resilient] over [Flaky] with the flag making it fail and
+    /// the number of times it was built.
+    fn resilient(lost: bool) -> (Resilient, Rc<Cell<bool>>, Rc<Cell<u32>>) {
+        resilient_at(lost, None, FsRoot::new("/nonexistent"))
+    }
+
+    fn resilient_at(
+        lost: bool,
+        pci_bus_id: Option<&'static str>,
+        root: FsRoot,
+    ) -> (Resilient, Rc<Cell<bool>>, Rc<Cell<u32>>) {
+        let failing = Rc::new(Cell::new(false));
+        let builds = Rc::new(Cell::new(0));
+
+        let build: Build = {
+            let failing = Rc::clone(&failing);
+            let builds = Rc::clone(&builds);
+
+            Box::new(move || {
+                builds.set(builds.get() + 1);
+                Ok(Box::new(Flaky {
+                    failing: Rc::clone(&failing),
+                    lost,
+                    device_info: Arc::new(DeviceInfo {
+                        vendor: "amd".to_string(),
+                        pci_bus_id: pci_bus_id.map(str::to_owned),
+                        ..Default::default()
+                    }),
+                }))
+            })
+        };
+
+        (
+            Resilient::new(build, root, Duration::from_secs(1), Duration::from_secs(3)),
+            failing,
+            builds,
+        )
+    }
+
+    /// Test that retries back off exponentially up to the maximum, returning
+    /// the last error in the meantime.
+    #[test]
+    fn test_backoff() {
+        let (resilient, failing, builds) = resilient(false);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(resilient.compute_at(at(0)).is_ok());
+        assert_eq!(resilient.device_info().vendor, "amd");
+
+        failing.set(true);
+        assert!(resilient.compute_at(at(1)).is_err());
+
+        // Waiting 1s, then 2s, then capped at 3s.
+        let Err(error) = resilient.compute_at(at(1)) else {
+            panic!("expected the last error");
+        };
+        assert_eq!(error.to_string(), "Failed to read the sensors");
+        assert!(resilient.compute_at(at(2)).is_err());
+        assert!(resilient.compute_at(at(3)).is_err());
+        assert_eq!(resilient.backoff.borrow().retry_at, Some(at(4)));
+        assert!(resilient.compute_at(at(4)).is_err());
+        assert_eq!(resilient.backoff.borrow().retry_at, Some(at(7)));
+
+        // Dropped after the third failure in a row, and built again on the
+        // next attempt.
+        assert_eq!(builds.get(), 1);
+
+        failing.set(false);
+        assert!(resilient.compute_at(at(6)).is_err());
+        assert!(resilient.compute_at(at(7)).is_ok());
+        assert_eq!(builds.get(), 2);
+        assert_eq!(resilient.backoff.borrow().failures, 0);
+    }
+
+    /// Test that the backend is built again right after it reported
+    /// [DeviceLost].
+    #[test]
+    fn test_reinit_when_lost() {
+        let (resilient, failing, builds) = resilient(true);
+        let now = Instant::now();
+
+        failing.set(true);
+        assert!(resilient.compute_at(now).is_err());
+
+        failing.set(false);
+        assert!(resilient.compute_at(now + Duration::from_secs(1)).is_ok());
+        assert_eq!(builds.get(), 2);
+    }
+
+    #[test]
+    fn test_build_error() {
+        let resilient = Resilient::new(
+            Box::new(|| Err(eyre!("No supported GPU found"))),
+            FsRoot::new("/nonexistent"),
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+        );
+
+        assert!(resilient.compute().is_err());
+        assert!(resilient.compute_force().is_err());
+        assert_eq!(resilient.device_info().vendor, "");
+    }
+
+    /// Creates a card and its PCI device in a fixture tree.
+    fn plug(root: &Path, card: &str, pci_bus_id: &str) {
+        fs::create_dir_all(root.join("sys/class/drm").join(card)).unwrap();
+        fs::create_dir_all(root.join("sys/bus/pci/devices").join(pci_bus_id)).unwrap();
+    }
+
+    fn unplug(root: &Path, card: &str, pci_bus_id: &str) {
+        fs::remove_dir_all(root.join("sys/class/drm").join(card)).unwrap();
+        fs::remove_dir_all(root.join("sys/bus/pci/devices").join(pci_bus_id)).unwrap();
+    }
+
+    /// Test that an unplugged GPU is reported as disconnected, and that the
+    /// backend is built again once it is plugged back in.
+    #[test]
+    fn test_hot_plug() {
+        let dir = fs_root::temp_dir("hotplug");
+        plug(&dir, "card0", "0000:00:02.0");
+        plug(&dir, "card1", "0000:03:00.0");
+
+        let (resilient, _, builds) =
+            resilient_at(false, Some("0000:03:00.0"), FsRoot::new(dir.clone()));
+        let now = Instant::now();
+
+        assert!(resilient.compute_at(now).is_ok());
+        assert!(resilient.compute_at(now).is_ok());
+        assert_eq!(builds.get(), 1);
+
+        unplug(&dir, "card1", "0000:03:00.0");
+        let Err(error) = resilient.compute_at(now) else {
+            panic!("expected the GPU to be disconnected");
+        };
+        assert!(error.downcast_ref::<Disconnected>().is_some());
+        assert_eq!(builds.get(), 1);
+
+        plug(&dir, "card1", "0000:03:00.0");
+        assert!(resilient.compute_at(now).is_ok());
+        assert_eq!(builds.get(), 2);
+
+        // Other cards coming and going rebuild the backend too, as they may
+        // change which GPU is picked.
+        unplug(&dir, "card0", "0000:00:02.0");
+        assert!(resilient.compute_at(now).is_ok());
+        assert_eq!(builds.get(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Test that a new card is tried right away while no backend could be
+    /// built.
+    #[test]
+    fn test_attach_resets_backoff() {
+        let dir = fs_root::temp_dir("attach");
+
+        let root = FsRoot::new(dir.clone());
+        let resilient = Resilient::new(
+            {
+                let root = root.clone();
+                Box::new(move || {
+                    if root.drm_cards().is_empty() {
+                        return Err(eyre!("No supported GPU found"));
+                    }
+                    Ok(Box::new(Flaky {
+                        failing: Rc::new(Cell::new(false)),
+                        lost: false,
+                        device_info: Arc::default(),
+                    }))
+                })
+            },
+            root,
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        );
+        let now = Instant::now();
+
+        assert!(resilient.compute_at(now).is_err());
+        assert!(resilient.compute_at(now).is_err());
+
+        plug(&dir, "card0", "0000:03:00.0");
+        assert!(resilient.compute_at(now).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}